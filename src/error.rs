@@ -39,6 +39,78 @@ pub enum FarmError {
 
     #[error("Numeric overflow // underflow ")]
     NumericOverflow,
+
+    #[error("Invalid vesting schedule ")]
+    InvalidSchedule,
+
+    #[error("Signer is not the platform owner ")]
+    Unauthorized,
+
+    #[error("Vesting schedule can't change after the first purchase ")]
+    ScheduleLocked,
+
+    #[error("Wallet reached the maximum number of purchases ")]
+    TooManyPurchases,
+
+    #[error("Sale is not open ")]
+    SaleNotActive,
+
+    #[error("Sale end must come after sale start ")]
+    InvalidSaleWindow,
+
+    #[error("Purchase exceeds the sale hard cap ")]
+    HardCapExceeded,
+
+    #[error("Purchase is below the minimum ticket ")]
+    PurchaseBelowMinimum,
+
+    #[error("Purchase exceeds the per wallet maximum ")]
+    WalletCapExceeded,
+
+    #[error("Buyer is not on the allowlist ")]
+    NotAllowlisted,
+
+    #[error("Purchase exceeds the allowlisted allocation ")]
+    AllocationExceeded,
+
+    #[error("Platform is paused ")]
+    PlatformPaused,
+
+    #[error("Sale has not ended yet ")]
+    SaleNotEnded,
+
+    #[error("Soft cap has not been reached ")]
+    SoftCapNotReached,
+
+    #[error("Refunds are only open after a sale that missed its soft cap ")]
+    RefundNotAvailable,
+
+    #[error("Position is not revocable ")]
+    NotRevocable,
+
+    #[error("Position has been revoked ")]
+    PositionTerminated,
+
+    #[error("Invalid admin set ")]
+    InvalidAdminSet,
+
+    #[error("Clock is behind a recorded timestamp ")]
+    ClockSkew,
+
+    #[error("Invalid token price ")]
+    InvalidPrice,
+
+    #[error("Fee is above 10000 basis points ")]
+    InvalidFee,
+
+    #[error("Payment mint table is full ")]
+    PaymentMintTableFull,
+
+    #[error("Hard cap is above the tokens in the vault ")]
+    HardCapAboveVault,
+
+    #[error("Soft cap is above the hard cap ")]
+    SoftCapAboveHardCap,
 }
 impl From<FarmError> for ProgramError {
     fn from(e: FarmError) -> Self {
@@ -1,5 +1,6 @@
 #![allow(clippy::too_many_arguments)]
 
+use arrayref::array_ref;
 use solana_program::{
     instruction::{AccountMeta, Instruction},
     msg,
@@ -8,22 +9,131 @@ use solana_program::{
 };
 use std::mem;
 
+use crate::state::{FeeMode, VestingMode, VestingTranche, MAX_VESTING_TRANCHES};
+
 #[repr(C)]
 #[derive(Clone, Debug, PartialEq)]
 pub enum PriveteSellInstruction {
     // Init platform
     InitializePlatform{
-        args: (u64, u64 ,u64,u64,u64,u64,u64,u64,u64,u64), 
+        args: (u64, u64 ,u64,u64,u64,u64), 
+        vesting_mode: VestingMode,
+        cliff: u64,
+        sale_start: u64,
+        sale_end: u64,
+        hard_cap: u64,
+        min_purchase: u64,
+        max_purchase_per_wallet: u64,
+        merkle_root: [u8; 32],
+        soft_cap: u64,
+        revocable: bool,
+        transfer_requires_approval: bool,
+        token_price_den: u64, //args.3 is the numerator
+        fee_recipient: Pubkey, //args.5 is the fee in basis points
+        fee_mode: FeeMode,
+        schedule: Vec<VestingTranche>,
       },
 
     //Private selling
     PrivateSell{
-        amount:u64
+        amount:u64,
+        allocation: u64, //allowlisted allocation of the buyer, part of its merkle leaf
+        proof: Vec<[u8; 32]>, //merkle proof of the buyer, empty when the platform has no allowlist
     },
 
     //Claim vesting amount
     Claim,
 
+    // Owner only: change pricing and fees, and the schedule until the first purchase
+    UpdatePlatform{
+        args: (u64, u64, u64, u64), // (vesting_period, token_price, init_stage, fee_bps)
+        vesting_mode: VestingMode,
+        cliff: u64,
+        token_price_den: u64,
+        schedule: Vec<VestingTranche>,
+    },
+
+    // Owner only: open and close the sale at the given unix timestamps, sale_end 0 => no end
+    SetSaleWindow{
+        sale_start: u64,
+        sale_end: u64,
+    },
+
+    // Owner only: least tokens per purchase and most tokens per wallet, 0 => no cap
+    SetPurchaseLimits{
+        min_purchase: u64,
+        max_purchase_per_wallet: u64,
+    },
+
+    // Owner only: allowlist of (buyer, allocation) leaves, all zero => anyone can buy
+    SetMerkleRoot{
+        merkle_root: [u8; 32],
+    },
+
+    // Owner only: stop PrivateSell, and Claim too when pause_claims is set
+    Pause{
+        pause_claims: bool,
+    },
+
+    // Owner only: resume PrivateSell and Claim
+    Unpause,
+
+    // Owner only: move the unsold vault tokens to the owner once the sale has ended
+    WithdrawUnsold,
+
+    // Owner only: move the escrowed payments to the treasury once the soft cap is met
+    WithdrawProceeds,
+
+    // Buyer gets its payments back when the sale ended below the soft cap
+    Refund,
+
+    // Owner only: settle what a revocable position has vested and take back the rest
+    Revoke,
+
+    // Owner only: vest `amount` vault tokens to a beneficiary on the platform schedule without a purchase
+    CreateGrant{
+        amount: u64,
+        start_timestamp: u64, //unix timestamp the grant vests from, 0 => now
+        revocable: bool,
+    },
+
+    // Beneficiary moves its whole position to a new wallet, co-signed by the owner when the platform asks for it
+    TransferPosition,
+
+    // Anyone can claim for a beneficiary, the tokens only go to its associated token account
+    CrankClaim,
+
+    // Beneficiary closes its fully claimed position and gets the rent back
+    CloseUserPosition,
+
+    // Owner only: sweep the vault and close it with the platform once every position is settled
+    ClosePlatform,
+
+    // Owner only: offer the platform to new_owner, the default pubkey withdraws the offer
+    ProposeOwner{
+        new_owner: Pubkey,
+    },
+
+    // Proposed owner takes over the platform
+    AcceptOwner,
+
+    // Owner only: replace the owner by `threshold` of `admins` for owner only instructions, empty admins => owner again
+    SetAdmins{
+        threshold: u8,
+        admins: Vec<Pubkey>,
+    },
+
+    // Owner only: wallet whose token accounts receive the fees
+    SetFeeRecipient{
+        fee_recipient: Pubkey,
+    },
+
+    // Owner only: accept another payment mint at its own price, paid into its own treasury
+    AddPaymentMint{
+        token_price: u64,
+        token_price_den: u64,
+    },
+
 }
 
 impl PriveteSellInstruction {
@@ -34,11 +144,82 @@ impl PriveteSellInstruction {
 
         Ok(match tag {
             0 => Self::InitializePlatform{
-                args: Self::unpack_data(rest)?,            },
+                args: Self::unpack_data(rest)?,
+                vesting_mode: Self::unpack_vesting_mode(rest.get(48..).unwrap_or_default())?,
+                cliff: Self::unpack_amount(rest.get(49..).unwrap_or_default())?,
+                sale_start: Self::unpack_amount(rest.get(57..).unwrap_or_default())?,
+                sale_end: Self::unpack_amount(rest.get(65..).unwrap_or_default())?,
+                hard_cap: Self::unpack_amount(rest.get(73..).unwrap_or_default())?,
+                min_purchase: Self::unpack_amount(rest.get(81..).unwrap_or_default())?,
+                max_purchase_per_wallet: Self::unpack_amount(rest.get(89..).unwrap_or_default())?,
+                merkle_root: Self::unpack_hash(rest.get(97..).unwrap_or_default())?,
+                soft_cap: Self::unpack_amount(rest.get(129..).unwrap_or_default())?,
+                revocable: Self::unpack_bool(rest.get(137..).unwrap_or_default())?,
+                transfer_requires_approval: Self::unpack_bool(rest.get(138..).unwrap_or_default())?,
+                token_price_den: Self::unpack_amount(rest.get(139..).unwrap_or_default())?,
+                fee_recipient: Pubkey::new_from_array(Self::unpack_hash(rest.get(147..).unwrap_or_default())?),
+                fee_mode: Self::unpack_fee_mode(rest.get(179..).unwrap_or_default())?,
+                schedule: Self::unpack_schedule(rest.get(180..).unwrap_or_default())?,
+            },
             1 => Self::PrivateSell{
                 amount: Self::unpack_amount(rest)?,
+                allocation: Self::unpack_amount(rest.get(8..).unwrap_or_default())?,
+                proof: Self::unpack_proof(rest.get(16..).unwrap_or_default())?,
             },
             2 => Self::Claim,
+            3 => Self::UpdatePlatform{
+                args: Self::unpack_update_data(rest)?,
+                vesting_mode: Self::unpack_vesting_mode(rest.get(32..).unwrap_or_default())?,
+                cliff: Self::unpack_amount(rest.get(33..).unwrap_or_default())?,
+                token_price_den: Self::unpack_amount(rest.get(41..).unwrap_or_default())?,
+                schedule: Self::unpack_schedule(rest.get(49..).unwrap_or_default())?,
+            },
+            4 => Self::SetSaleWindow{
+                sale_start: Self::unpack_amount(rest)?,
+                sale_end: Self::unpack_amount(rest.get(8..).unwrap_or_default())?,
+            },
+            5 => Self::SetPurchaseLimits{
+                min_purchase: Self::unpack_amount(rest)?,
+                max_purchase_per_wallet: Self::unpack_amount(rest.get(8..).unwrap_or_default())?,
+            },
+            6 => Self::SetMerkleRoot{
+                merkle_root: Self::unpack_hash(rest)?,
+            },
+            7 => Self::Pause{
+                pause_claims: Self::unpack_bool(rest)?,
+            },
+            8 => Self::Unpause,
+            9 => Self::WithdrawUnsold,
+            10 => Self::WithdrawProceeds,
+            11 => Self::Refund,
+            12 => Self::Revoke,
+            13 => Self::CreateGrant{
+                amount: Self::unpack_amount(rest)?,
+                start_timestamp: Self::unpack_amount(rest.get(8..).unwrap_or_default())?,
+                revocable: Self::unpack_bool(rest.get(16..).unwrap_or_default())?,
+            },
+            14 => Self::TransferPosition,
+            15 => Self::CrankClaim,
+            16 => Self::CloseUserPosition,
+            17 => Self::ClosePlatform,
+            18 => Self::ProposeOwner{
+                new_owner: Pubkey::new_from_array(Self::unpack_hash(rest)?),
+            },
+            19 => Self::AcceptOwner,
+            20 => Self::SetAdmins{
+                threshold: *rest.first().ok_or(ProgramError::InvalidInstructionData)?,
+                admins: Self::unpack_proof(rest.get(1..).unwrap_or_default())?
+                    .into_iter()
+                    .map(Pubkey::new_from_array)
+                    .collect(),
+            },
+            21 => Self::SetFeeRecipient{
+                fee_recipient: Pubkey::new_from_array(Self::unpack_hash(rest)?),
+            },
+            22 => Self::AddPaymentMint{
+                token_price: Self::unpack_amount(rest)?,
+                token_price_den: Self::unpack_amount(rest.get(8..).unwrap_or_default())?,
+            },
 
             _ => return Err(ProgramError::InvalidAccountData),
         })
@@ -47,7 +228,7 @@ impl PriveteSellInstruction {
     pub fn pack(&self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(mem::size_of::<Self>());
         match &*self {
-            Self::InitializePlatform {args } => {
+            Self::InitializePlatform {args, vesting_mode, cliff, sale_start, sale_end, hard_cap, min_purchase, max_purchase_per_wallet, merkle_root, soft_cap, revocable, transfer_requires_approval, token_price_den, fee_recipient, fee_mode, schedule } => {
                 buf.push(0);
                 buf.extend_from_slice(&args.0.to_le_bytes());
                 buf.extend_from_slice(&args.1.to_le_bytes());
@@ -55,24 +236,104 @@ impl PriveteSellInstruction {
                 buf.extend_from_slice(&args.3.to_le_bytes());
                 buf.extend_from_slice(&args.4.to_le_bytes());
                 buf.extend_from_slice(&args.5.to_le_bytes());
-                buf.extend_from_slice(&args.6.to_le_bytes());
-                buf.extend_from_slice(&args.7.to_le_bytes());
-                buf.extend_from_slice(&args.8.to_le_bytes());
-                buf.extend_from_slice(&args.9.to_le_bytes());
-
+                buf.push(*vesting_mode as u8);
+                buf.extend_from_slice(&cliff.to_le_bytes());
+                buf.extend_from_slice(&sale_start.to_le_bytes());
+                buf.extend_from_slice(&sale_end.to_le_bytes());
+                buf.extend_from_slice(&hard_cap.to_le_bytes());
+                buf.extend_from_slice(&min_purchase.to_le_bytes());
+                buf.extend_from_slice(&max_purchase_per_wallet.to_le_bytes());
+                buf.extend_from_slice(merkle_root);
+                buf.extend_from_slice(&soft_cap.to_le_bytes());
+                buf.push(*revocable as u8);
+                buf.push(*transfer_requires_approval as u8);
+                buf.extend_from_slice(&token_price_den.to_le_bytes());
+                buf.extend_from_slice(fee_recipient.as_ref());
+                buf.push(*fee_mode as u8);
+                Self::pack_schedule(schedule, &mut buf);
 
             }
-            Self::PrivateSell { amount } => {
+            Self::PrivateSell { amount, allocation, proof } => {
                 buf.push(1);
                 buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&allocation.to_le_bytes());
+                buf.push(proof.len() as u8);
+                for node in proof {
+                    buf.extend_from_slice(node);
+                }
+            }
+            Self::Claim => buf.push(2),
+            Self::UpdatePlatform { args, vesting_mode, cliff, token_price_den, schedule } => {
+                buf.push(3);
+                buf.extend_from_slice(&args.0.to_le_bytes());
+                buf.extend_from_slice(&args.1.to_le_bytes());
+                buf.extend_from_slice(&args.2.to_le_bytes());
+                buf.extend_from_slice(&args.3.to_le_bytes());
+                buf.push(*vesting_mode as u8);
+                buf.extend_from_slice(&cliff.to_le_bytes());
+                buf.extend_from_slice(&token_price_den.to_le_bytes());
+                Self::pack_schedule(schedule, &mut buf);
+            }
+            Self::SetSaleWindow { sale_start, sale_end } => {
+                buf.push(4);
+                buf.extend_from_slice(&sale_start.to_le_bytes());
+                buf.extend_from_slice(&sale_end.to_le_bytes());
+            }
+            Self::SetPurchaseLimits { min_purchase, max_purchase_per_wallet } => {
+                buf.push(5);
+                buf.extend_from_slice(&min_purchase.to_le_bytes());
+                buf.extend_from_slice(&max_purchase_per_wallet.to_le_bytes());
+            }
+            Self::SetMerkleRoot { merkle_root } => {
+                buf.push(6);
+                buf.extend_from_slice(merkle_root);
+            }
+            Self::Pause { pause_claims } => {
+                buf.push(7);
+                buf.push(*pause_claims as u8);
+            }
+            Self::Unpause => buf.push(8),
+            Self::WithdrawUnsold => buf.push(9),
+            Self::WithdrawProceeds => buf.push(10),
+            Self::Refund => buf.push(11),
+            Self::Revoke => buf.push(12),
+            Self::CreateGrant { amount, start_timestamp, revocable } => {
+                buf.push(13);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&start_timestamp.to_le_bytes());
+                buf.push(*revocable as u8);
+            }
+            Self::TransferPosition => buf.push(14),
+            Self::CrankClaim => buf.push(15),
+            Self::CloseUserPosition => buf.push(16),
+            Self::ClosePlatform => buf.push(17),
+            Self::ProposeOwner { new_owner } => {
+                buf.push(18);
+                buf.extend_from_slice(new_owner.as_ref());
+            }
+            Self::AcceptOwner => buf.push(19),
+            Self::SetAdmins { threshold, admins } => {
+                buf.push(20);
+                buf.push(*threshold);
+                buf.push(admins.len() as u8);
+                for admin in admins {
+                    buf.extend_from_slice(admin.as_ref());
+                }
+            }
+            Self::SetFeeRecipient { fee_recipient } => {
+                buf.push(21);
+                buf.extend_from_slice(fee_recipient.as_ref());
+            }
+            Self::AddPaymentMint { token_price, token_price_den } => {
+                buf.push(22);
+                buf.extend_from_slice(&token_price.to_le_bytes());
+                buf.extend_from_slice(&token_price_den.to_le_bytes());
             }
-
-            _ => todo!(),
         }
         buf
     }
 
-    fn unpack_data(input: &[u8]) -> Result<(u64,u64,u64,u64,u64,u64,u64,u64,u64,u64), ProgramError> {
+    fn unpack_data(input: &[u8]) -> Result<(u64,u64,u64,u64,u64,u64), ProgramError> {
         let amount1 = input
             .get(0..8)
             .and_then(|slice| slice.try_into().ok())
@@ -106,31 +367,87 @@ impl PriveteSellInstruction {
             .and_then(|slice| slice.try_into().ok())
             .map(u64::from_le_bytes)
             .ok_or(ProgramError::InvalidInstructionData)?;
-        let amount7 = input
-            .get(48..56)
-            .and_then(|slice| slice.try_into().ok())
-            .map(u64::from_le_bytes)
+
+        Ok((amount1,amount2,amount3,amount4,amount5,amount6))
+    }
+
+    fn unpack_update_data(input: &[u8]) -> Result<(u64, u64, u64, u64), ProgramError> {
+        Ok((
+            Self::unpack_amount(input)?,
+            Self::unpack_amount(input.get(8..).unwrap_or_default())?,
+            Self::unpack_amount(input.get(16..).unwrap_or_default())?,
+            Self::unpack_amount(input.get(24..).unwrap_or_default())?,
+        ))
+    }
+
+    /// Schedule layout: one byte tranche count followed by `(offset, percentage)` u64 pairs.
+    fn unpack_schedule(input: &[u8]) -> Result<Vec<VestingTranche>, ProgramError> {
+        let (count, rest) = input
+            .split_first()
             .ok_or(ProgramError::InvalidInstructionData)?;
-        
-        let amount8 = input
-            .get(56..64)
-            .and_then(|slice| slice.try_into().ok())
-            .map(u64::from_le_bytes)
+        let count = *count as usize;
+        if count > MAX_VESTING_TRANCHES {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        let tranches = rest
+            .get(..count * VestingTranche::LEN)
             .ok_or(ProgramError::InvalidInstructionData)?;
+        Ok(tranches
+            .chunks_exact(VestingTranche::LEN)
+            .map(|tranche| VestingTranche::unpack_from_slice(array_ref![tranche, 0, VestingTranche::LEN]))
+            .collect())
+    }
+
+    fn pack_schedule(schedule: &[VestingTranche], buf: &mut Vec<u8>) {
+        buf.push(schedule.len() as u8);
+        for tranche in schedule {
+            buf.extend_from_slice(&tranche.offset.to_le_bytes());
+            buf.extend_from_slice(&tranche.percentage.to_le_bytes());
+        }
+    }
+
+    fn unpack_vesting_mode(input: &[u8]) -> Result<VestingMode, ProgramError> {
+        input
+            .first()
+            .and_then(|mode| VestingMode::from_u8(*mode))
+            .ok_or(ProgramError::InvalidInstructionData)
+    }
+
+    fn unpack_fee_mode(input: &[u8]) -> Result<FeeMode, ProgramError> {
+        input
+            .first()
+            .and_then(|mode| FeeMode::from_u8(*mode))
+            .ok_or(ProgramError::InvalidInstructionData)
+    }
+
+    fn unpack_bool(input: &[u8]) -> Result<bool, ProgramError> {
+        match input.first() {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
 
-        let amount9 = input
-            .get(64..72)
+    fn unpack_hash(input: &[u8]) -> Result<[u8; 32], ProgramError> {
+        input
+            .get(..32)
             .and_then(|slice| slice.try_into().ok())
-            .map(u64::from_le_bytes)
+            .ok_or(ProgramError::InvalidInstructionData)
+    }
+
+    /// Proof layout: one byte node count followed by the 32 byte nodes, leaf side first.
+    /// Admin sets use the same layout.
+    fn unpack_proof(input: &[u8]) -> Result<Vec<[u8; 32]>, ProgramError> {
+        let (count, rest) = input
+            .split_first()
             .ok_or(ProgramError::InvalidInstructionData)?;
-        
-        let amount10 = input
-            .get(72..80)
-            .and_then(|slice| slice.try_into().ok())
-            .map(u64::from_le_bytes)
+        let nodes = rest
+            .get(..*count as usize * 32)
             .ok_or(ProgramError::InvalidInstructionData)?;
-
-        Ok((amount1,amount2,amount3,amount4,amount5,amount6,amount7,amount8,amount9,amount10))
+        Ok(nodes
+            .chunks_exact(32)
+            .map(|node| *array_ref![node, 0, 32])
+            .collect())
     }
 
     fn unpack_amount(input: &[u8]) -> Result<u64, ProgramError> {
@@ -2,6 +2,7 @@ use num_traits::CheckedDiv;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
+    keccak::hashv,
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
@@ -14,10 +15,17 @@ use solana_program::{
 use crate::{
     error::FarmError,
     instruction::PriveteSellInstruction,
-    state::{PlatForm, UserState},
+    state::{
+        FeeMode, PaymentMint, PlatForm, Purchase, UserState, VestingMode, VestingTranche,
+        BPS_DENOMINATOR, CLOSE_GRACE_PERIOD, MAX_ADMINS, MAX_PAYMENT_MINTS, MAX_PURCHASES,
+        MAX_VESTING_TRANCHES,
+    },
 };
 use spl_associated_token_account;
-use spl_token::{instruction::transfer, state::Account as TokenAccount};
+use spl_token::{
+    instruction::{close_account, transfer},
+    state::{Account as TokenAccount, Mint},
+};
 use std::cell::RefCell;
 use std::str::FromStr;
 pub struct Processor;
@@ -29,27 +37,123 @@ impl Processor {
     ) -> ProgramResult {
         let instruction = PriveteSellInstruction::unpack(instruction_data)?;
         match instruction {
-            PriveteSellInstruction::InitializePlatform {args} => {
+            PriveteSellInstruction::InitializePlatform {args, vesting_mode, cliff, sale_start, sale_end, hard_cap, min_purchase, max_purchase_per_wallet, merkle_root, soft_cap, revocable, transfer_requires_approval, token_price_den, fee_recipient, fee_mode, schedule} => {
                 msg!("Instruction:INIT PLATFORM");
-                return Self::process_init_platform(accounts, program_id,args);
+                Self::process_init_platform(accounts, program_id,args,vesting_mode,cliff,sale_start,sale_end,hard_cap,min_purchase,max_purchase_per_wallet,merkle_root,soft_cap,revocable,transfer_requires_approval,token_price_den,fee_recipient,fee_mode,schedule)
             }
             //PrivateSell means it is from buy from the user and sell from the vesting account
-            PriveteSellInstruction::PrivateSell { amount} => {
+            PriveteSellInstruction::PrivateSell { amount, allocation, proof} => {
                 msg!("Instruction:Sell!!!!!");
-                return Self::process_sell(accounts, program_id,amount);
+                Self::process_sell(accounts, program_id,amount,allocation,proof)
             }
             PriveteSellInstruction::Claim {} => {
                 msg!("Instruction:claim");
-                return Self::process_claim(accounts, program_id);
+                Self::process_claim(accounts, program_id, false)
+            }
+            PriveteSellInstruction::UpdatePlatform {args, vesting_mode, cliff, token_price_den, schedule} => {
+                msg!("Instruction:UPDATE PLATFORM");
+                Self::process_update_platform(accounts, program_id,args,vesting_mode,cliff,token_price_den,schedule)
+            }
+            PriveteSellInstruction::SetSaleWindow {sale_start, sale_end} => {
+                msg!("Instruction:SET SALE WINDOW");
+                Self::process_set_sale_window(accounts, program_id,sale_start,sale_end)
+            }
+            PriveteSellInstruction::SetPurchaseLimits {min_purchase, max_purchase_per_wallet} => {
+                msg!("Instruction:SET PURCHASE LIMITS");
+                Self::process_set_purchase_limits(accounts, program_id,min_purchase,max_purchase_per_wallet)
+            }
+            PriveteSellInstruction::SetMerkleRoot {merkle_root} => {
+                msg!("Instruction:SET MERKLE ROOT");
+                Self::process_set_merkle_root(accounts, program_id,merkle_root)
+            }
+            PriveteSellInstruction::Pause {pause_claims} => {
+                msg!("Instruction:PAUSE");
+                Self::process_set_paused(accounts, program_id,true,pause_claims)
+            }
+            PriveteSellInstruction::Unpause => {
+                msg!("Instruction:UNPAUSE");
+                Self::process_set_paused(accounts, program_id,false,false)
+            }
+            PriveteSellInstruction::WithdrawUnsold => {
+                msg!("Instruction:WITHDRAW UNSOLD");
+                Self::process_withdraw_unsold(accounts, program_id)
+            }
+            PriveteSellInstruction::WithdrawProceeds => {
+                msg!("Instruction:WITHDRAW PROCEEDS");
+                Self::process_withdraw_proceeds(accounts, program_id)
+            }
+            PriveteSellInstruction::Refund => {
+                msg!("Instruction:REFUND");
+                Self::process_refund(accounts, program_id)
+            }
+            PriveteSellInstruction::Revoke => {
+                msg!("Instruction:REVOKE");
+                Self::process_revoke(accounts, program_id)
+            }
+            PriveteSellInstruction::CreateGrant {amount, start_timestamp, revocable} => {
+                msg!("Instruction:CREATE GRANT");
+                Self::process_create_grant(accounts, program_id, amount, start_timestamp, revocable)
+            }
+            PriveteSellInstruction::TransferPosition => {
+                msg!("Instruction:TRANSFER POSITION");
+                Self::process_transfer_position(accounts, program_id)
+            }
+            PriveteSellInstruction::CrankClaim => {
+                msg!("Instruction:CRANK CLAIM");
+                Self::process_claim(accounts, program_id, true)
+            }
+            PriveteSellInstruction::CloseUserPosition => {
+                msg!("Instruction:CLOSE USER POSITION");
+                Self::process_close_user_position(accounts, program_id)
+            }
+            PriveteSellInstruction::ClosePlatform => {
+                msg!("Instruction:CLOSE PLATFORM");
+                Self::process_close_platform(accounts, program_id)
+            }
+            PriveteSellInstruction::ProposeOwner {new_owner} => {
+                msg!("Instruction:PROPOSE OWNER");
+                Self::process_propose_owner(accounts, program_id, new_owner)
+            }
+            PriveteSellInstruction::AcceptOwner => {
+                msg!("Instruction:ACCEPT OWNER");
+                Self::process_accept_owner(accounts, program_id)
+            }
+            PriveteSellInstruction::SetAdmins {threshold, admins} => {
+                msg!("Instruction:SET ADMINS");
+                Self::process_set_admins(accounts, program_id, threshold, admins)
+            }
+            PriveteSellInstruction::SetFeeRecipient {fee_recipient} => {
+                msg!("Instruction:SET FEE RECIPIENT");
+                Self::process_set_fee_recipient(accounts, program_id, fee_recipient)
+            }
+            PriveteSellInstruction::AddPaymentMint {token_price, token_price_den} => {
+                msg!("Instruction:ADD PAYMENT MINT");
+                Self::process_add_payment_mint(accounts, program_id, token_price, token_price_den)
             }
             
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn process_init_platform(
         accounts: &[AccountInfo],
         program_id: &Pubkey,
-        args:(u64,u64,u64,u64,u64,u64,u64,u64,u64,u64),
+        args:(u64,u64,u64,u64,u64,u64),
+        vesting_mode: VestingMode,
+        cliff: u64,
+        sale_start: u64,
+        sale_end: u64,
+        hard_cap: u64,
+        min_purchase: u64,
+        max_purchase_per_wallet: u64,
+        merkle_root: [u8; 32],
+        soft_cap: u64,
+        revocable: bool,
+        transfer_requires_approval: bool,
+        token_price_den: u64,
+        fee_recipient: Pubkey,
+        fee_mode: FeeMode,
+        schedule: Vec<VestingTranche>,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
 
@@ -67,6 +171,12 @@ impl Processor {
 
         let token_program = next_account_info(account_info_iter)?;
 
+        let payment_mint_account = next_account_info(account_info_iter)?; //mint buyers pay with e.g. USDC
+
+        let vesting_mint_account = next_account_info(account_info_iter)?; //mint of the vault e.g. SOLG
+
+        let treasury_token_account = next_account_info(account_info_iter)?; //(treasory wallet) payment mint account the payments go to
+
         //creating the state account of the vesting program
         invoke(
             &create_account(
@@ -108,15 +218,76 @@ impl Processor {
         platform_data.is_initialized = true;
         platform_data.owner = *owner_account.key;
         platform_data.vesting_per = args.1; //vesting percentage - what user will get immediately after the txn
-        platform_data.vesting_period=args.2; //vesting time in sec => 240/4 = 60s for each txn to take place 
+        platform_data.vesting_period=args.2; //vesting time in sec over which the linear mode releases the tokens
         // token price for 1 USDC (can be any SPL token based on my config) with our SOLG token. 
         platform_data.token_price=args.3; //Hence, as per current login => 1 USDC = 10 SOLG
+        platform_data.token_price_den=token_price_den; //e.g. 7 / 20 => 1 USDC = 0.35 SOLG
+        Self::check_price(&platform_data.primary_payment_terms())?;
         platform_data.init_stage=args.4;//The percentage of the token qty at immediate txn of token buying which user will receive
-        platform_data.stage_1=args.5; //The percentage of the token qty at 1st settlement (60s) of token buying which user will receive
-        platform_data.stage_2=args.6; //The percentage of the token qty at IInd settlement (120s) of token buying which user will receive
-        platform_data.stage_3=args.7; //The percentage of the token qty at IIIrd settlement (180s) of token buying which user will receive
-        platform_data.stage_4=args.8;//The percentage of the token qty at IVth(Final) settlement (240s) of token buying which user will receive
-        platform_data.platform_fess=args.9;//The percentage of the token qty at IVth(Final) settlement (240s) of token buying which user will receive
+        platform_data.fee_bps=args.5;//basis points of the payment charged on top of it as platform fees, 100 => 1%
+        platform_data.fee_recipient=fee_recipient; //wallet the fees are paid to, apart from the treasury
+        platform_data.fee_mode=fee_mode; //PaymentToken => on top of the payment, VestingToken => withheld from the tokens
+        platform_data.vesting_mode=vesting_mode; //Staged => schedule tranches, Linear => per second over vesting_period
+        platform_data.cliff=cliff; //sec after buying before anything beyond the init stage can be claimed
+
+        //(offset, percentage) tranches e.g. 12 monthly tranches, each released `offset` sec after buying
+        Self::set_schedule(&mut platform_data, &schedule)?;
+        //least tokens to sell before the owner gets the proceeds, 0 => no soft cap and no escrow
+        platform_data.soft_cap = soft_cap;
+        Self::check_fee(&platform_data)?;
+        platform_data.revocable = revocable; //positions bought from now on can be revoked by the owner
+        platform_data.transfer_requires_approval = transfer_requires_approval; //owner co-signs TransferPosition
+        Self::set_sale_window(&mut platform_data, sale_start, sale_end)?; //unix timestamps, sale_end 0 => no end
+        //most tokens the sale can sell, 0 => everything deposited below
+        platform_data.hard_cap = if hard_cap == 0 { args.0 } else { hard_cap };
+        //a soft cap that can't be sold would refund every purchase
+        if platform_data.soft_cap > platform_data.hard_cap {
+            return Err(FarmError::SoftCapAboveHardCap.into());
+        }
+        platform_data.total_sold = 0;
+        platform_data.min_purchase = min_purchase; //least tokens a single purchase can buy
+        platform_data.max_purchase_per_wallet = max_purchase_per_wallet; //most tokens a wallet can buy in total, 0 => no cap
+        platform_data.merkle_root = merkle_root; //root of the (buyer, allocation) allowlist, all zero => anyone can buy
+
+        //the vault holding the unsold tokens has to be owned by the platform PDA
+        let vault = TokenAccount::unpack(&pda_reward_token_account.try_borrow_data()?)?;
+        if vault.owner != pda {
+            return Err(FarmError::InvalidTokenAccountOwner.into());
+        }
+        platform_data.vault_token_account = *pda_reward_token_account.key;
+
+        //prices are in whole tokens, the mint decimals turn them into base units, see token_amount
+        if vault.mint != *vesting_mint_account.key {
+            return Err(FarmError::InvalidMint.into());
+        }
+        let vesting_mint = Mint::unpack(&vesting_mint_account.try_borrow_data()?)?;
+        let payment_mint = Mint::unpack(&payment_mint_account.try_borrow_data()?)?;
+        platform_data.payment_mint = *payment_mint_account.key;
+        platform_data.payment_decimals = payment_mint.decimals;
+        platform_data.vesting_decimals = vesting_mint.decimals;
+        if TokenAccount::unpack(&treasury_token_account.try_borrow_data()?)?.mint
+            != platform_data.payment_mint
+        {
+            return Err(FarmError::InvalidMint.into());
+        }
+        platform_data.treasury = *treasury_token_account.key;
+
+        //a soft cap sale keeps every payment in this one escrow, owned by the platform PDA
+        if soft_cap != 0 {
+            let escrow_token_account = next_account_info(account_info_iter)?;
+            if *escrow_token_account.key == platform_data.vault_token_account {
+                msg!("vault can't be the escrow");
+                return Err(ProgramError::InvalidAccountData);
+            }
+            let escrow = TokenAccount::unpack(&escrow_token_account.try_borrow_data()?)?;
+            if escrow.owner != pda {
+                return Err(FarmError::InvalidTokenAccountOwner.into());
+            }
+            if escrow.mint != platform_data.payment_mint {
+                return Err(FarmError::InvalidMint.into());
+            }
+            platform_data.escrow_token_account = *escrow_token_account.key;
+        }
 
 
         let transfer_token = transfer(
@@ -139,6 +310,13 @@ impl Processor {
                 token_program.clone(), //use to transfer the SPL or SOL token
             ],
         )?;
+
+        //a hard cap the vault can't cover would end purchases with a failing token transfer
+        let vault_amount = TokenAccount::unpack(&pda_reward_token_account.try_borrow_data()?)?.amount;
+        if platform_data.hard_cap > vault_amount {
+            msg!("vault holds {}", vault_amount);
+            return Err(FarmError::HardCapAboveVault.into());
+        }
         msg!("platform state{:?}",platform_data);
 
         PlatForm::pack(
@@ -153,6 +331,7 @@ impl Processor {
         msg!("entry ********************************");
         let account_info_iter = &mut accounts.iter();
 
+        let payer = next_account_info(account_info_iter)?; //buyer, or the owner for grants
         let user = next_account_info(account_info_iter)?;
         let user_state_account = next_account_info(account_info_iter)?;
         let platform_state_account = next_account_info(account_info_iter)?;
@@ -175,14 +354,14 @@ impl Processor {
 
         invoke_signed(
             &create_account(
-                user.key,
+                payer.key,
                 user_state_account.key,
                 Rent::default().minimum_balance(UserState::LEN),
                 UserState::LEN as u64,
                 program_id,
             ),
             &[
-                user.clone(),
+                payer.clone(),
                 user_state_account.clone(),
                 system_program.clone(),
             ],
@@ -208,7 +387,10 @@ impl Processor {
         user_data.buying_timestamp = 0;
         user_data.vesting_amount = 0;
         user_data.buying_amount = 0;
-        user_data.counter = 0;
+        user_data.purchase_count = 0;
+        user_data.revocable = false;
+        user_data.terminated = false;
+        user_data.purchased_total = 0;
 
         // platform_data. => mutation
 
@@ -220,7 +402,13 @@ impl Processor {
         Ok(())
     }
 
-    pub fn process_sell(accounts: &[AccountInfo], program_id: &Pubkey,amount:u64) -> ProgramResult {
+    pub fn process_sell(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        amount: u64,
+        allocation: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
         //user = buyer in context of FRONTEND
         //user = receiver in context of Vesting Program/Contract
@@ -242,6 +430,7 @@ impl Processor {
 
         let token_program = next_account_info(account_info_iter)?;//Solana token program
         let system_program = next_account_info(account_info_iter)?;//Solana system program
+        let fee_recipient_token_account = next_account_info(account_info_iter)?;//fee recipient account of the payment mint, unchecked placeholder without a fee
 
         let user_pda_seed = &[(user.key).as_ref(), (platform_state.key).as_ref()];
 
@@ -258,18 +447,28 @@ impl Processor {
         //owner is not program id then it is first time for the user interacting with the vesting contract
         if user_state_account.owner != program_id {
             let user_init_accounts = &[
+                user.clone(), //payer
                 user.clone(), //user
                 user_state_account.clone(), //user state account
                 platform_state.clone(), // platform state
                 system_program.clone(), // system program
             ];
 
-            Self::process_user_init(user_init_accounts, program_id);
+            Self::process_user_init(user_init_accounts, program_id)?;
         };
         let mut user_data = UserState::unpack_unchecked(&user_state_account.try_borrow_data()?)?;
+        if user_data.terminated {
+            return Err(FarmError::PositionTerminated.into());
+        }
 
-        if user_data.is_initialized == true{
-            return Err(ProgramError::AccountAlreadyInitialized)?;
+        //a fully claimed position starts over, otherwise the purchase is added next to the earlier ones
+        if !user_data.is_initialized {
+            user_data.vesting_amount = 0;
+            user_data.buying_amount = 0;
+            user_data.purchase_count = 0;
+        }
+        if user_data.purchase_count as usize >= MAX_PURCHASES {
+            return Err(FarmError::TooManyPurchases.into());
         }
 
 
@@ -293,40 +492,149 @@ impl Processor {
         let mut platform_state_info =
             PlatForm::unpack_unchecked(&platform_state.try_borrow_data()?)?;
 
-        msg!("amount{}",amount.clone());
-        msg!("platform_fess{}",platform_state_info.platform_fess);
-
+        if platform_state_info.paused {
+            msg!("platform is paused");
+            return Err(FarmError::PlatformPaused.into());
+        }
 
-        let transfer_token = transfer(
-            token_program.key, 
-            user_sending_token_account.key, //USDC from the user (buyer)
-            owner_recining_token_account.key, //USDC to the owner (treasory)
-            user.key, //authority of the token sender
-            &[], //not needed as already signed from the FRONTEND
-            amount.clone()+((amount * platform_state_info.platform_fess)/100),
-        )?;
-        msg!("Calling the token program to transfer tokens user to owner token account...");
-        invoke(
-            &transfer_token,
-            &[
-                user_sending_token_account.clone(), //source
-                owner_recining_token_account.clone(), //destination
-                user.clone(), //authority
-                token_program.clone(), //token program
-            ],
-        )?;
+        if *pda_token_account.key != platform_state_info.vault_token_account {
+            msg!("wrong vault");
+            return Err(ProgramError::InvalidAccountData);
+        }
 
         //set up clock
         let system_clock = Clock::get()?;
-        msg!("222");
 
-        user_data.buying_timestamp = system_clock.unix_timestamp.clone() as u64;
+        //purchases are only accepted while the sale window is open
+        if !platform_state_info.is_sale_open(Self::unix_timestamp(&system_clock)?) {
+            msg!("sale is not open");
+            return Err(FarmError::SaleNotActive.into());
+        }
+
+        //the mint paid with picks the price and the treasury, see AddPaymentMint
+        let payment_mint = TokenAccount::unpack(&user_sending_token_account.try_borrow_data()?)?.mint;
+        let payment_terms = platform_state_info
+            .payment_terms(&payment_mint)
+            .ok_or(FarmError::InvalidMint)?;
+
+        msg!("amount{}",amount.clone());
+        msg!("fee_bps{}",platform_state_info.fee_bps);
+
+        let (total_token_recived_to_user, init_stage_amount, paid_amount, fee_token_amount) =
+            Self::sale_amounts(&platform_state_info, &payment_terms, amount)?;
+        //rounding down can leave a tiny payment with nothing to vest
+        if total_token_recived_to_user == 0 {
+            return Err(FarmError::PurchaseBelowMinimum.into());
+        }
+
+        //the sale can never hand out more than its hard cap, fees in vesting tokens included
+        platform_state_info.total_sold = platform_state_info
+            .total_sold
+            .checked_add(total_token_recived_to_user)
+            .and_then(|total_sold| total_sold.checked_add(fee_token_amount))
+            .ok_or(FarmError::NumericOverflow)?;
+        if platform_state_info.total_sold > platform_state_info.hard_cap {
+            msg!("only {} tokens left", platform_state_info.remaining_allocation());
+            return Err(FarmError::HardCapExceeded.into());
+        }
+        if platform_state_info.is_soft_cap_reached() {
+            platform_state_info.soft_cap_reached = true;
+        }
+
+        //ticket floor per purchase and cap per wallet over all of its purchases, claimed ones included
+        if total_token_recived_to_user < platform_state_info.min_purchase {
+            return Err(FarmError::PurchaseBelowMinimum.into());
+        }
+        let wallet_total = user_data
+            .purchased_total
+            .checked_add(total_token_recived_to_user)
+            .ok_or(FarmError::NumericOverflow)?;
+        if platform_state_info.max_purchase_per_wallet != 0
+            && wallet_total > platform_state_info.max_purchase_per_wallet
+        {
+            return Err(FarmError::WalletCapExceeded.into());
+        }
+
+        //invite only: the buyer and its allocation must be a leaf of the allowlist, 0 allocation => no cap
+        if platform_state_info.merkle_root != [0; 32] {
+            let leaf = hashv(&[user.key.as_ref(), &allocation.to_le_bytes()]).0;
+            if !Self::verify_merkle_proof(&proof, &platform_state_info.merkle_root, leaf) {
+                msg!("buyer is not on the allowlist");
+                return Err(FarmError::NotAllowlisted.into());
+            }
+            if allocation != 0 && wallet_total > allocation {
+                return Err(FarmError::AllocationExceeded.into());
+            }
+        }
 
+        //with a soft cap the payment is held in a PDA owned escrow instead of the treasury until the cap is met,
+        //refunds pay back from that one escrow so only the init payment mint is accepted
+        if platform_state_info.soft_cap != 0 {
+            if payment_mint != platform_state_info.payment_mint {
+                msg!("soft cap sales are paid in the init payment mint");
+                return Err(FarmError::InvalidMint.into());
+            }
+            Self::check_escrow(
+                &platform_state_info,
+                owner_recining_token_account,
+                user_sending_token_account,
+                &pda,
+            )?;
+        } else if *owner_recining_token_account.key != payment_terms.treasury {
+            msg!("wrong treasury");
+            return Err(ProgramError::InvalidAccountData);
+        }
 
-        let total_token_recived_to_user=amount*platform_state_info.token_price;
+        //principal to the treasury and fee to the fee recipient, both stay in the escrow while it can be refunded
+        let fee_amount = paid_amount
+            .checked_sub(amount)
+            .ok_or(FarmError::NumericOverflow)?;
+        let fee_destination = if platform_state_info.soft_cap != 0 {
+            platform_state_info.escrowed_fees = platform_state_info
+                .escrowed_fees
+                .checked_add(fee_amount)
+                .ok_or(FarmError::NumericOverflow)?;
+            owner_recining_token_account
+        } else {
+            //the account is still passed, but without a fee nothing is sent to it, so its owner and mint aren't checked
+            if fee_amount != 0 {
+                Self::check_fee_account(
+                    &platform_state_info,
+                    fee_recipient_token_account,
+                    user_sending_token_account,
+                )?;
+            }
+            fee_recipient_token_account
+        };
 
-        let init_stage_amount=(total_token_recived_to_user*platform_state_info.init_stage)/100;
+        for (destination, transfer_amount) in [
+            (owner_recining_token_account, amount), //USDC to the owner (treasory) or the escrow
+            (fee_destination, fee_amount),
+        ] {
+            if transfer_amount == 0 {
+                continue;
+            }
+            let transfer_token = transfer(
+                token_program.key, 
+                user_sending_token_account.key, //USDC from the user (buyer)
+                destination.key,
+                user.key, //authority of the token sender
+                &[], //not needed as already signed from the FRONTEND
+                transfer_amount,
+            )?;
+            msg!("Calling the token program to transfer tokens user to owner token account...");
+            invoke(
+                &transfer_token,
+                &[
+                    user_sending_token_account.clone(), //source
+                    destination.clone(), //destination
+                    user.clone(), //authority
+                    token_program.clone(), //token program
+                ],
+            )?;
+        }
 
+        user_data.buying_timestamp = Self::unix_timestamp(&system_clock)?;
 
 
         //////
@@ -354,6 +662,33 @@ impl Processor {
             ]],
         )?;
         ////
+
+        //a fee withheld from the tokens goes from the vault to the fee vault, a vesting mint account of the fee recipient
+        if fee_token_amount != 0 {
+            Self::check_fee_account(&platform_state_info, fee_recipient_token_account, pda_token_account)?;
+            let transfer_token = transfer(
+                token_program.key,
+                pda_token_account.key,
+                fee_recipient_token_account.key,
+                &pda,
+                &[],
+                fee_token_amount,
+            )?;
+            invoke_signed(
+                &transfer_token,
+                &[
+                    pda_token_account.clone(),
+                    fee_recipient_token_account.clone(),
+                    pda_account.clone(),
+                    token_program.clone(),
+                ],
+                &[&[
+                    pda_prefix.as_bytes(),
+                    platform_state.key.as_ref(),
+                    &[nonce],
+                ]],
+            )?;
+        }
     
 
         let transfer_token = transfer(
@@ -381,10 +716,39 @@ impl Processor {
         )?;
        
 
-        user_data.vesting_amount = total_token_recived_to_user-init_stage_amount;
-        user_data.buying_amount = total_token_recived_to_user;
+        //each purchase vests on its own from its buying timestamp
+        user_data.purchases[user_data.purchase_count as usize] = Purchase {
+            buying_timestamp: user_data.buying_timestamp,
+            buying_amount: total_token_recived_to_user,
+            paid_amount,
+            fee_amount,
+        };
+        user_data.purchase_count += 1;
+        user_data.vesting_amount = user_data
+            .vesting_amount
+            .checked_add(
+                total_token_recived_to_user
+                    .checked_sub(init_stage_amount)
+                    .ok_or(FarmError::NumericOverflow)?,
+            )
+            .ok_or(FarmError::NumericOverflow)?;
+        user_data.buying_amount = user_data
+            .buying_amount
+            .checked_add(total_token_recived_to_user)
+            .ok_or(FarmError::NumericOverflow)?;
+        user_data.purchased_total = wallet_total;
+        if !user_data.is_initialized {
+            user_data.revocable = platform_state_info.revocable;
+            platform_state_info.open_positions = platform_state_info
+                .open_positions
+                .checked_add(1)
+                .ok_or(FarmError::NumericOverflow)?;
+        }
         user_data.is_initialized = true;
 
+        //the schedule can't change anymore once someone bought with it
+        platform_state_info.schedule_locked = true;
+
 
         
 
@@ -398,7 +762,10 @@ impl Processor {
         Ok(())
     }
 
-    pub fn process_claim(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    /// Sends everything unlocked so far to the beneficiary. A plain claim is
+    /// signed by the beneficiary; a crank claim can be sent by anyone but only
+    /// pays out to the beneficiary's associated token account of the vault mint.
+    pub fn process_claim(accounts: &[AccountInfo], program_id: &Pubkey, crank: bool) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
 
         msg!("entered ******************");
@@ -442,101 +809,52 @@ impl Processor {
         let mut platform_state_info =
             PlatForm::unpack_unchecked(&platform_state.try_borrow_data()?)?;
 
-        let settlement_duration=platform_state_info.vesting_period/4;
-        msg!("settlement_duration{}",settlement_duration);
-
-
-        let system_clock = Clock::get()?;
-
-
-        let user_duration=system_clock.unix_timestamp.clone() as u64 - user_data.buying_timestamp ;
-        let mut claim_amount=0;
-        msg!("user_duration{}",user_duration);
-        /**
-         * 240
-         * 
-         * condition 1
-         * 60
-         * 20 
-         * 
-         * condition 2
-         * 60
-         * 65
-         * 
-         * 3:
-         * 60
-         * 125
-         * 100
-         * stage 0 = 20
-         * stage 1 = 20  - 20
-         * stage 2 = 10  
-         * stage 3 = 30
-         * stage 4 = 20
-         */
-
-        //20
-        if user_duration >=settlement_duration && user_duration < settlement_duration *2 {
-            if user_data.counter==0 {
-                claim_amount=claim_amount+((user_data.buying_amount*platform_state_info.stage_1)/100);
-                user_data.counter=user_data.counter+1;
-
-            }
-           
-        } else if user_duration >= settlement_duration *2 && user_duration < settlement_duration *3 {
-            if user_data.counter != 2 {
-                claim_amount=claim_amount+((user_data.buying_amount*platform_state_info.stage_2)/100); //40
-                if user_data.counter==0{
-                    claim_amount=claim_amount+((user_data.buying_amount*platform_state_info.stage_1)/100); // 80
-                    user_data.counter=user_data.counter+2; //2
-    
-                };
-                user_data.counter=user_data.counter+1; //2
-
-            }
-          
-        } else if user_duration >=settlement_duration *3 && user_duration <settlement_duration * 4{
-            if user_data.counter != 3{
-                claim_amount=claim_amount+((user_data.buying_amount*platform_state_info.stage_3)/100);
-                if user_data.counter==0{
-                    claim_amount=claim_amount+((user_data.buying_amount*platform_state_info.stage_1)/100)
-                                  +((user_data.buying_amount*platform_state_info.stage_2)/100);
-                    user_data.counter=user_data.counter+3;
-    
-                };
-                if user_data.counter==1{
-                    claim_amount=claim_amount+((user_data.buying_amount*platform_state_info.stage_2)/100);
-                    user_data.counter=user_data.counter+2;
-    
-                }
-                user_data.counter=user_data.counter+1;
+        if platform_state_info.claims_paused {
+            msg!("claims are paused");
+            return Err(FarmError::PlatformPaused.into());
+        }
 
+        if crank {
+            if *pda_token_account.key != platform_state_info.vault_token_account {
+                msg!("wrong vault");
+                return Err(ProgramError::InvalidAccountData);
             }
-         
+            let vault = TokenAccount::unpack(&pda_token_account.try_borrow_data()?)?;
+            let user_ata =
+                spl_associated_token_account::get_associated_token_address(user.key, &vault.mint);
+            if user_ata != *user_reciving_token_account.key {
+                msg!("claim must go to the beneficiary ATA {}", user_ata);
+                return Err(FarmError::InvalidTokenAccountOwner.into());
+            }
+        } else if !user.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
 
-        }else if user_duration >settlement_duration * 4{
+        //buyers may still be refunded until the soft cap is met, so nothing can be claimed before that
+        if !platform_state_info.is_soft_cap_reached() {
+            return Err(FarmError::SoftCapNotReached.into());
+        }
 
-            if user_data.counter !=4{
-                claim_amount=claim_amount+((user_data.buying_amount*platform_state_info.stage_4)/100);
-                if user_data.counter==0{
-                    claim_amount=claim_amount+((user_data.buying_amount*platform_state_info.stage_1)/100)+
-                    ((user_data.buying_amount*platform_state_info.stage_2)/100)+
-                    ((user_data.buying_amount*platform_state_info.stage_3)/100);
-                }
-                else if user_data.counter==1{
-                    claim_amount=claim_amount+((user_data.buying_amount*platform_state_info.stage_2)/100)+
-                    ((user_data.buying_amount*platform_state_info.stage_3)/100);
-                }
-                else if user_data.counter==2{
-                    claim_amount=claim_amount+((user_data.buying_amount*platform_state_info.stage_3)/100);
-    
-                };
-                //no need for the user counter as it is false
-                user_data.counter=user_data.counter+1;
-                user_data.is_initialized=false;
+        if user_data.terminated {
+            return Err(FarmError::PositionTerminated.into());
+        }
 
+        let system_clock = Clock::get()?;
 
-            }
-    
+        let (unlocked_amount, claim_amount) = Self::claimable_amount(
+            &platform_state_info,
+            &user_data,
+            Self::unix_timestamp(&system_clock)?,
+        )?;
+        msg!("claim_amount{}",claim_amount);
+
+        //no need for the user state anymore once everything is unlocked
+        if unlocked_amount == user_data.buying_amount && user_data.is_initialized {
+            user_data.is_initialized = false;
+            platform_state_info.open_positions = platform_state_info
+                .open_positions
+                .checked_sub(1)
+                .ok_or(FarmError::NumericOverflow)?;
         }
 
         let transfer_token = transfer(
@@ -564,7 +882,10 @@ impl Processor {
         )?;
 
       
-        user_data.vesting_amount =  user_data.vesting_amount-claim_amount;
+        user_data.vesting_amount = user_data
+            .vesting_amount
+            .checked_sub(claim_amount)
+            .ok_or(FarmError::NumericOverflow)?;
 
 
 
@@ -578,4 +899,1597 @@ impl Processor {
         Ok(())
     }
 
+    pub fn process_update_platform(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        args: (u64, u64, u64, u64),
+        vesting_mode: VestingMode,
+        cliff: u64,
+        token_price_den: u64,
+        schedule: Vec<VestingTranche>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        let schedule_changed = platform_data.vesting_period != args.0
+            || platform_data.init_stage != args.2
+            || platform_data.vesting_mode != vesting_mode
+            || platform_data.cliff != cliff
+            || platform_data.schedule() != schedule.as_slice();
+        if schedule_changed && platform_data.schedule_locked {
+            return Err(FarmError::ScheduleLocked.into());
+        }
+
+        platform_data.vesting_period = args.0;
+        platform_data.token_price = args.1;
+        platform_data.init_stage = args.2;
+        platform_data.fee_bps = args.3;
+        platform_data.vesting_mode = vesting_mode;
+        platform_data.cliff = cliff;
+        platform_data.token_price_den = token_price_den;
+        Self::check_price(&platform_data.primary_payment_terms())?;
+        Self::check_fee(&platform_data)?;
+        Self::set_schedule(&mut platform_data, &schedule)?;
+        msg!("platform state{:?}",platform_data);
+
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn process_set_sale_window(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        sale_start: u64,
+        sale_end: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        //buyers of a soft cap sale count on the refunds opening at sale_end, so the window is fixed once it started
+        let system_clock = Clock::get()?;
+        if platform_data.soft_cap != 0 && Self::unix_timestamp(&system_clock)? >= platform_data.sale_start {
+            msg!("soft cap sale already started");
+            return Err(FarmError::InvalidSaleWindow.into());
+        }
+
+        Self::set_sale_window(&mut platform_data, sale_start, sale_end)?;
+        msg!("sale window {} - {}", sale_start, sale_end);
+
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn process_set_purchase_limits(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        min_purchase: u64,
+        max_purchase_per_wallet: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        platform_data.min_purchase = min_purchase;
+        platform_data.max_purchase_per_wallet = max_purchase_per_wallet;
+        msg!("purchase limits {} - {}", min_purchase, max_purchase_per_wallet);
+
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn process_set_merkle_root(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        merkle_root: [u8; 32],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        platform_data.merkle_root = merkle_root;
+
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Sends whatever is left in the vault back to the owner once the sale has ended.
+    ///
+    /// Sold tokens are moved to the buyer's PDA token account by `process_sell`,
+    /// so everything still in the vault is unsold and nothing owed to a buyer is touched.
+    pub fn process_withdraw_unsold(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+        let pda_token_account = next_account_info(account_info_iter)?; //vault of the platform
+        let destination_token_account = next_account_info(account_info_iter)?; //any account of the vesting mint picked by the owner
+        let pda_account = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        let system_clock = Clock::get()?;
+        if !platform_data.is_sale_ended(Self::unix_timestamp(&system_clock)?) {
+            return Err(FarmError::SaleNotEnded.into());
+        }
+
+        let pda_prefix = "Private_selling";
+        let pda_seed = &[pda_prefix.as_bytes(), (platform_state_account.key).as_ref()];
+        let (pda, nonce) = Pubkey::find_program_address(pda_seed, program_id);
+
+        if pda != *pda_account.key {
+            msg!("wrong pda");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if *pda_token_account.key != platform_data.vault_token_account {
+            msg!("wrong vault");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let unsold_amount = TokenAccount::unpack(&pda_token_account.try_borrow_data()?)?.amount;
+        msg!("unsold {}", unsold_amount);
+
+        let transfer_token = transfer(
+            token_program.key,
+            pda_token_account.key,
+            destination_token_account.key,
+            &pda,
+            &[],
+            unsold_amount,
+        )?;
+        invoke_signed(
+            &transfer_token,
+            &[
+                pda_token_account.clone(),
+                destination_token_account.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[
+                pda_prefix.as_bytes(),
+                platform_state_account.key.as_ref(),
+                &[nonce],
+            ]],
+        )?;
+
+        //the vault is empty now, nothing more can be sold or granted
+        platform_data.hard_cap = platform_data.total_sold;
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Sends the escrowed payments to the owner once the soft cap is met.
+    pub fn process_withdraw_proceeds(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+        let escrow_token_account = next_account_info(account_info_iter)?; //PDA owned account holding the payments
+        let destination_token_account = next_account_info(account_info_iter)?; //treasury account of the payment mint
+        let pda_account = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let fee_recipient_token_account = next_account_info(account_info_iter)?; //fee recipient account of the payment mint, unchecked placeholder without fees
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        if !platform_data.is_soft_cap_reached() {
+            return Err(FarmError::SoftCapNotReached.into());
+        }
+
+        let pda_prefix = "Private_selling";
+        let pda_seed = &[pda_prefix.as_bytes(), (platform_state_account.key).as_ref()];
+        let (pda, nonce) = Pubkey::find_program_address(pda_seed, program_id);
+
+        if pda != *pda_account.key {
+            msg!("wrong pda");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Self::check_escrow(&platform_data, escrow_token_account, destination_token_account, &pda)?;
+
+        //the principal goes to the treasury of the escrowed mint, like PrivateSell without a soft cap
+        let escrow = TokenAccount::unpack(&escrow_token_account.try_borrow_data()?)?;
+        let payment_terms = platform_data
+            .payment_terms(&escrow.mint)
+            .ok_or(FarmError::InvalidMint)?;
+        if *destination_token_account.key != payment_terms.treasury {
+            msg!("wrong treasury");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let balance = escrow.amount;
+        let fees = platform_data.escrowed_fees.min(balance);
+        if fees != 0 {
+            Self::check_fee_account(&platform_data, fee_recipient_token_account, escrow_token_account)?;
+        }
+        let proceeds = balance - fees;
+        msg!("proceeds {} fees {}", proceeds, fees);
+
+        for (destination, transfer_amount) in [
+            (destination_token_account, proceeds),
+            (fee_recipient_token_account, fees),
+        ] {
+            if transfer_amount == 0 {
+                continue;
+            }
+            let transfer_token = transfer(
+                token_program.key,
+                escrow_token_account.key,
+                destination.key,
+                &pda,
+                &[],
+                transfer_amount,
+            )?;
+            invoke_signed(
+                &transfer_token,
+                &[
+                    escrow_token_account.clone(),
+                    destination.clone(),
+                    pda_account.clone(),
+                    token_program.clone(),
+                ],
+                &[&[
+                    pda_prefix.as_bytes(),
+                    platform_state_account.key.as_ref(),
+                    &[nonce],
+                ]],
+            )?;
+        }
+
+        platform_data.escrowed_fees = 0;
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Gives a buyer its payments back from the escrow when the sale ended
+    /// below the soft cap, and returns its tokens to the vault.
+    pub fn process_refund(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let user = next_account_info(account_info_iter)?; //buyer, must sign
+        let user_state_account = next_account_info(account_info_iter)?;
+        let user_pda_token_account = next_account_info(account_info_iter)?; //holds the locked tokens of the buyer
+        let platform_state = next_account_info(account_info_iter)?;
+        let escrow_token_account = next_account_info(account_info_iter)?; //PDA owned account holding the payments
+        let user_refund_token_account = next_account_info(account_info_iter)?; //payment mint account of the buyer
+        let pda_token_account = next_account_info(account_info_iter)?; //vault of the platform
+        let pda_account = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+
+        if !user.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if platform_state.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let user_pda_seed = &[(user.key).as_ref(), (platform_state.key).as_ref()];
+        let (user_state, nonce1) = Pubkey::find_program_address(user_pda_seed, program_id);
+        if user_state != *user_state_account.key {
+            msg!("user_state_acc wrong");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let pda_prefix = "Private_selling";
+        let pda_seed = &[pda_prefix.as_bytes(), (platform_state.key).as_ref()];
+        let (pda, nonce) = Pubkey::find_program_address(pda_seed, program_id);
+        if pda != *pda_account.key {
+            msg!("wrong pda");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let mut platform_state_info = PlatForm::unpack(&platform_state.try_borrow_data()?)?;
+        if *pda_token_account.key != platform_state_info.vault_token_account {
+            msg!("wrong vault");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Self::check_escrow(&platform_state_info, escrow_token_account, user_refund_token_account, &pda)?;
+
+        let system_clock = Clock::get()?;
+        if platform_state_info.soft_cap == 0
+            || platform_state_info.is_soft_cap_reached()
+            || !platform_state_info.is_sale_ended(Self::unix_timestamp(&system_clock)?)
+        {
+            return Err(FarmError::RefundNotAvailable.into());
+        }
+
+        let mut user_data = UserState::unpack(&user_state_account.try_borrow_data()?)?;
+        let refund_amount = user_data
+            .purchases()
+            .iter()
+            .try_fold(0u64, |total, purchase| total.checked_add(purchase.paid_amount))
+            .ok_or(FarmError::NumericOverflow)?;
+        //the fees are paid back too, so they no longer go to the fee recipient
+        let refund_fees = user_data
+            .purchases()
+            .iter()
+            .try_fold(0u64, |total, purchase| total.checked_add(purchase.fee_amount))
+            .ok_or(FarmError::NumericOverflow)?;
+        platform_state_info.escrowed_fees = platform_state_info
+            .escrowed_fees
+            .checked_sub(refund_fees)
+            .ok_or(FarmError::NumericOverflow)?;
+        msg!("refund {} tokens back {}", refund_amount, user_data.vesting_amount);
+
+        let transfer_token = transfer(
+            token_program.key,
+            escrow_token_account.key,
+            user_refund_token_account.key,
+            &pda,
+            &[],
+            refund_amount,
+        )?;
+        invoke_signed(
+            &transfer_token,
+            &[
+                escrow_token_account.clone(),
+                user_refund_token_account.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[pda_prefix.as_bytes(), platform_state.key.as_ref(), &[nonce]]],
+        )?;
+
+        //nothing was released before the soft cap, so the whole purchase goes back to the vault
+        let transfer_token = transfer(
+            token_program.key,
+            user_pda_token_account.key,
+            pda_token_account.key,
+            &user_state,
+            &[],
+            user_data.vesting_amount,
+        )?;
+        invoke_signed(
+            &transfer_token,
+            &[
+                user_pda_token_account.clone(),
+                pda_token_account.clone(),
+                user_state_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[user.key.as_ref(), platform_state.key.as_ref(), &[nonce1]]],
+        )?;
+
+        platform_state_info.total_sold = platform_state_info
+            .total_sold
+            .checked_sub(user_data.buying_amount)
+            .ok_or(FarmError::NumericOverflow)?;
+        if user_data.is_initialized {
+            platform_state_info.open_positions = platform_state_info
+                .open_positions
+                .checked_sub(1)
+                .ok_or(FarmError::NumericOverflow)?;
+        }
+        user_data.vesting_amount = 0;
+        user_data.buying_amount = 0;
+        user_data.purchase_count = 0;
+        user_data.is_initialized = false;
+
+        UserState::pack(user_data, &mut user_state_account.try_borrow_mut_data()?)?;
+        PlatForm::pack(
+            platform_state_info,
+            &mut platform_state.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Ends a revocable position: the beneficiary gets what has vested so far
+    /// and the still locked rest goes back to the vault, to be sold again.
+    pub fn process_revoke(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+        let user = next_account_info(account_info_iter)?; //beneficiary wallet of the position
+        let user_state_account = next_account_info(account_info_iter)?;
+        let user_pda_token_account = next_account_info(account_info_iter)?; //holds the locked tokens of the beneficiary
+        let user_reciving_token_account = next_account_info(account_info_iter)?; //beneficiary account for the vested part
+        let pda_token_account = next_account_info(account_info_iter)?; //vault of the platform, gets the unvested part
+        let token_program = next_account_info(account_info_iter)?;
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        if *pda_token_account.key != platform_data.vault_token_account {
+            msg!("wrong vault");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let user_pda_seed = &[(user.key).as_ref(), (platform_state_account.key).as_ref()];
+        let (user_state, nonce1) = Pubkey::find_program_address(user_pda_seed, program_id);
+        if user_state != *user_state_account.key || user_state_account.owner != program_id {
+            msg!("user_state_acc wrong");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let mut user_data = UserState::unpack_unchecked(&user_state_account.try_borrow_data()?)?;
+        if user_data.terminated {
+            return Err(FarmError::PositionTerminated.into());
+        }
+        if !user_data.is_initialized {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        if !user_data.revocable {
+            return Err(FarmError::NotRevocable.into());
+        }
+        //refunds would hand the payments back too, revoking has to wait for the soft cap
+        if !platform_data.is_soft_cap_reached() {
+            return Err(FarmError::SoftCapNotReached.into());
+        }
+
+        //the owner picks the accounts, so the vested part must really go to the beneficiary
+        let reciving_account = TokenAccount::unpack(&user_reciving_token_account.try_borrow_data()?)?;
+        if reciving_account.owner != *user.key {
+            return Err(FarmError::InvalidTokenAccountOwner.into());
+        }
+
+        let system_clock = Clock::get()?;
+        let (_, claim_amount) = Self::claimable_amount(
+            &platform_data,
+            &user_data,
+            Self::unix_timestamp(&system_clock)?,
+        )?;
+        let unvested_amount = user_data
+            .vesting_amount
+            .checked_sub(claim_amount)
+            .ok_or(FarmError::NumericOverflow)?;
+        msg!("vested {} unvested {}", claim_amount, unvested_amount);
+
+        let user_state_seeds: &[&[u8]] = &[user.key.as_ref(), platform_state_account.key.as_ref(), &[nonce1]];
+        for (destination, amount) in [
+            (user_reciving_token_account, claim_amount),
+            (pda_token_account, unvested_amount),
+        ] {
+            let transfer_token = transfer(
+                token_program.key,
+                user_pda_token_account.key,
+                destination.key,
+                &user_state,
+                &[],
+                amount,
+            )?;
+            invoke_signed(
+                &transfer_token,
+                &[
+                    user_pda_token_account.clone(),
+                    destination.clone(),
+                    user_state_account.clone(),
+                    token_program.clone(),
+                ],
+                &[user_state_seeds],
+            )?;
+        }
+
+        //the unvested part can be sold again, or taken out by WithdrawUnsold and ClosePlatform
+        platform_data.total_sold = platform_data
+            .total_sold
+            .checked_sub(unvested_amount)
+            .ok_or(FarmError::NumericOverflow)?;
+
+        user_data.vesting_amount = 0;
+        user_data.is_initialized = false;
+        user_data.terminated = true;
+        platform_data.open_positions = platform_data
+            .open_positions
+            .checked_sub(1)
+            .ok_or(FarmError::NumericOverflow)?;
+        UserState::pack(user_data, &mut user_state_account.try_borrow_mut_data()?)?;
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Vests `amount` vault tokens to `user` on the platform schedule, counted
+    /// from `start_timestamp`. The grant is kept like a purchase with nothing
+    /// paid, so the beneficiary collects it with `Claim`. Grants count towards
+    /// the hard cap but never towards the soft cap, so they can only be made
+    /// once the soft cap is reached and are never refunded.
+    pub fn process_create_grant(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        amount: u64,
+        start_timestamp: u64,
+        revocable: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign and pays for the user state
+        let user = next_account_info(account_info_iter)?; //beneficiary wallet
+        let user_state_account = next_account_info(account_info_iter)?;
+        let user_pda_token_account = next_account_info(account_info_iter)?; //token account owned by the user state PDA
+        let pda_token_account = next_account_info(account_info_iter)?; //vault of the platform
+        let pda_account = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        if amount == 0 {
+            msg!("nothing to grant");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if !platform_data.is_soft_cap_reached() {
+            return Err(FarmError::SoftCapNotReached.into());
+        }
+
+        //the vault holds the hard cap, a grant takes from it like a purchase
+        platform_data.total_sold = platform_data
+            .total_sold
+            .checked_add(amount)
+            .ok_or(FarmError::NumericOverflow)?;
+        if platform_data.total_sold > platform_data.hard_cap {
+            msg!("only {} tokens left", platform_data.remaining_allocation());
+            return Err(FarmError::HardCapExceeded.into());
+        }
+        if *pda_token_account.key != platform_data.vault_token_account {
+            msg!("wrong vault");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let pda_prefix = "Private_selling";
+        let pda_seed = &[pda_prefix.as_bytes(), (platform_state_account.key).as_ref()];
+        let (pda, nonce) = Pubkey::find_program_address(pda_seed, program_id);
+        if pda != *pda_account.key {
+            msg!("wrong pda");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let user_pda_seed = &[(user.key).as_ref(), (platform_state_account.key).as_ref()];
+        let (user_state, _) = Pubkey::find_program_address(user_pda_seed, program_id);
+        if user_state != *user_state_account.key {
+            msg!("user_state_acc wrong");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let user_pda_token = TokenAccount::unpack(&user_pda_token_account.try_borrow_data()?)?;
+        if user_pda_token.owner != user_state {
+            return Err(FarmError::InvalidTokenAccountOwner.into());
+        }
+
+        if user_state_account.owner != program_id {
+            let user_init_accounts = &[
+                owner_account.clone(), //payer
+                user.clone(), //user
+                user_state_account.clone(), //user state account
+                platform_state_account.clone(), // platform state
+                system_program.clone(), // system program
+            ];
+
+            Self::process_user_init(user_init_accounts, program_id)?;
+        }
+        let mut user_data = UserState::unpack_unchecked(&user_state_account.try_borrow_data()?)?;
+        if user_data.terminated {
+            return Err(FarmError::PositionTerminated.into());
+        }
+
+        //a fully claimed position starts over, otherwise the grant is added next to the earlier purchases
+        if !user_data.is_initialized {
+            user_data.vesting_amount = 0;
+            user_data.buying_amount = 0;
+            user_data.purchase_count = 0;
+            user_data.revocable = revocable;
+            platform_data.open_positions = platform_data
+                .open_positions
+                .checked_add(1)
+                .ok_or(FarmError::NumericOverflow)?;
+        } else if user_data.revocable != revocable {
+            msg!("position is already revocable {}", user_data.revocable);
+            return Err(ProgramError::InvalidArgument);
+        }
+        if user_data.purchase_count as usize >= MAX_PURCHASES {
+            return Err(FarmError::TooManyPurchases.into());
+        }
+
+        let system_clock = Clock::get()?;
+        let now = Self::unix_timestamp(&system_clock)?;
+        let start_timestamp = if start_timestamp == 0 { now } else { start_timestamp };
+        //a purchase in the future looks like clock skew to the claim math, grants can only be backdated
+        if start_timestamp > now {
+            msg!("grant can't start in the future");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let transfer_token = transfer(
+            token_program.key,
+            pda_token_account.key,
+            user_pda_token_account.key,
+            &pda,
+            &[],
+            amount,
+        )?;
+        invoke_signed(
+            &transfer_token,
+            &[
+                pda_token_account.clone(),
+                user_pda_token_account.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[
+                pda_prefix.as_bytes(),
+                platform_state_account.key.as_ref(),
+                &[nonce],
+            ]],
+        )?;
+
+        //nothing is released up front, the init stage is claimable with everything else
+        user_data.purchases[user_data.purchase_count as usize] = Purchase {
+            buying_timestamp: start_timestamp,
+            buying_amount: amount,
+            paid_amount: 0,
+            fee_amount: 0,
+        };
+        user_data.purchase_count += 1;
+        user_data.buying_timestamp = user_data.buying_timestamp.max(start_timestamp);
+        user_data.vesting_amount = user_data
+            .vesting_amount
+            .checked_add(amount)
+            .ok_or(FarmError::NumericOverflow)?;
+        user_data.buying_amount = user_data
+            .buying_amount
+            .checked_add(amount)
+            .ok_or(FarmError::NumericOverflow)?;
+        user_data.is_initialized = true;
+        msg!("granted {} from {}", amount, start_timestamp);
+
+        //the schedule can't change anymore once a grant vests with it
+        platform_data.schedule_locked = true;
+
+        UserState::pack(user_data, &mut user_state_account.try_borrow_mut_data()?)?;
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Moves a whole position, locked tokens, purchases and claim progress, to
+    /// the user state of `new_user`, which must not hold a position yet.
+    pub fn process_transfer_position(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let user = next_account_info(account_info_iter)?; //current beneficiary, must sign and pays for the new user state
+        let user_state_account = next_account_info(account_info_iter)?;
+        let user_pda_token_account = next_account_info(account_info_iter)?;
+        let new_user = next_account_info(account_info_iter)?; //new beneficiary wallet
+        let new_user_state_account = next_account_info(account_info_iter)?;
+        let new_user_pda_token_account = next_account_info(account_info_iter)?; //token account owned by the new user state PDA
+        let platform_state = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        if !user.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if platform_state.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let platform_state_info = PlatForm::unpack(&platform_state.try_borrow_data()?)?;
+        //moving locked tokens to another wallet is stopped by either breaker
+        if platform_state_info.paused || platform_state_info.claims_paused {
+            msg!("platform is paused");
+            return Err(FarmError::PlatformPaused.into());
+        }
+        if platform_state_info.transfer_requires_approval {
+            let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+            Self::check_owner(&platform_state_info, owner_account, accounts)?;
+        }
+
+        if user.key == new_user.key {
+            msg!("same beneficiary");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let user_pda_seed = &[(user.key).as_ref(), (platform_state.key).as_ref()];
+        let (user_state, nonce1) = Pubkey::find_program_address(user_pda_seed, program_id);
+        if user_state != *user_state_account.key || user_state_account.owner != program_id {
+            msg!("user_state_acc wrong");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let new_user_pda_seed = &[(new_user.key).as_ref(), (platform_state.key).as_ref()];
+        let (new_user_state, _) = Pubkey::find_program_address(new_user_pda_seed, program_id);
+        if new_user_state != *new_user_state_account.key {
+            msg!("new user_state_acc wrong");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let new_user_pda_token = TokenAccount::unpack(&new_user_pda_token_account.try_borrow_data()?)?;
+        if new_user_pda_token.owner != new_user_state {
+            return Err(FarmError::InvalidTokenAccountOwner.into());
+        }
+
+        let mut user_data = UserState::unpack_unchecked(&user_state_account.try_borrow_data()?)?;
+        if user_data.terminated {
+            return Err(FarmError::PositionTerminated.into());
+        }
+        if !user_data.is_initialized {
+            return Err(ProgramError::UninitializedAccount);
+        }
+
+        if new_user_state_account.owner != program_id {
+            let user_init_accounts = &[
+                user.clone(), //payer
+                new_user.clone(), //user
+                new_user_state_account.clone(), //user state account
+                platform_state.clone(), // platform state
+                system_program.clone(), // system program
+            ];
+
+            Self::process_user_init(user_init_accounts, program_id)?;
+        }
+        let new_user_data = UserState::unpack_unchecked(&new_user_state_account.try_borrow_data()?)?;
+        if new_user_data.terminated {
+            return Err(FarmError::PositionTerminated.into());
+        }
+        if new_user_data.is_initialized {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        let transfer_token = transfer(
+            token_program.key,
+            user_pda_token_account.key,
+            new_user_pda_token_account.key,
+            &user_state,
+            &[],
+            user_data.vesting_amount,
+        )?;
+        invoke_signed(
+            &transfer_token,
+            &[
+                user_pda_token_account.clone(),
+                new_user_pda_token_account.clone(),
+                user_state_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[user.key.as_ref(), platform_state.key.as_ref(), &[nonce1]]],
+        )?;
+        msg!("moved {} locked tokens to {}", user_data.vesting_amount, new_user.key);
+
+        //the purchases keep their timestamps, so vesting goes on exactly where it was,
+        //while each wallet keeps its own purchased total for the wallet cap and allocation
+        let new_user_data = UserState {
+            user: *new_user.key,
+            purchased_total: new_user_data.purchased_total,
+            ..user_data
+        };
+
+        //purchased_total stays, moving the position away doesn't free the wallet cap to buy again
+        user_data.vesting_amount = 0;
+        user_data.buying_amount = 0;
+        user_data.purchase_count = 0;
+        user_data.is_initialized = false;
+
+        UserState::pack(user_data, &mut user_state_account.try_borrow_mut_data()?)?;
+        UserState::pack(new_user_data, &mut new_user_state_account.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    /// Closes the user PDA token account and the `UserState` of a position with
+    /// nothing left to claim, the rent of both goes to the user. Revoked
+    /// positions stay, and so does the purchased total while the sale is on.
+    pub fn process_close_user_position(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let user = next_account_info(account_info_iter)?; //beneficiary, must sign and gets the rent
+        let user_state_account = next_account_info(account_info_iter)?;
+        let user_pda_token_account = next_account_info(account_info_iter)?;
+        let platform_state = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+
+        if !user.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let user_pda_seed = &[(user.key).as_ref(), (platform_state.key).as_ref()];
+        let (user_state, nonce1) = Pubkey::find_program_address(user_pda_seed, program_id);
+        if user_state != *user_state_account.key || user_state_account.owner != program_id {
+            msg!("user_state_acc wrong");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let user_data = UserState::unpack_unchecked(&user_state_account.try_borrow_data()?)?;
+        if user_data.is_initialized || user_data.vesting_amount != 0 {
+            msg!("position still vesting");
+            return Err(FarmError::IllegalAction.into());
+        }
+        //the terminated flag is what keeps a revoked wallet from buying again
+        if user_data.terminated {
+            return Err(FarmError::PositionTerminated.into());
+        }
+        //the wallet cap counts the purchased total, it can only go once nothing can be bought anymore
+        if platform_state.owner == program_id && user_data.purchased_total != 0 {
+            let platform_data = PlatForm::unpack(&platform_state.try_borrow_data()?)?;
+            let system_clock = Clock::get()?;
+            if !platform_data.is_sale_ended(Self::unix_timestamp(&system_clock)?) {
+                return Err(FarmError::SaleNotEnded.into());
+            }
+        }
+
+        let user_pda_token = TokenAccount::unpack(&user_pda_token_account.try_borrow_data()?)?;
+        if user_pda_token.owner != user_state {
+            return Err(FarmError::InvalidTokenAccountOwner.into());
+        }
+
+        //the token program refuses to close an account that still holds tokens
+        let close_token_account = close_account(
+            token_program.key,
+            user_pda_token_account.key,
+            user.key,
+            &user_state,
+            &[],
+        )?;
+        invoke_signed(
+            &close_token_account,
+            &[
+                user_pda_token_account.clone(),
+                user.clone(),
+                user_state_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[user.key.as_ref(), platform_state.key.as_ref(), &[nonce1]]],
+        )?;
+
+        let user_lamports = user.lamports();
+        **user.lamports.borrow_mut() = user_lamports
+            .checked_add(user_state_account.lamports())
+            .ok_or(FarmError::NumericOverflow)?;
+        **user_state_account.lamports.borrow_mut() = 0;
+        user_state_account.try_borrow_mut_data()?.fill(0);
+        msg!("closed position of {}", user.key);
+
+        Ok(())
+    }
+
+    /// Sends what is left in the vault to the owner and closes the vault and
+    /// the platform state, once no position is open or `CLOSE_GRACE_PERIOD`
+    /// after the sale ended. Positions still open then can't claim anymore.
+    /// A soft cap escrow has to be empty first: proceeds and fees withdrawn and
+    /// refunds paid; it is closed too.
+    pub fn process_close_platform(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign and gets the rent
+        let pda_token_account = next_account_info(account_info_iter)?; //vault of the platform
+        let destination_token_account = next_account_info(account_info_iter)?; //owner account of the vesting mint
+        let pda_account = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        if *pda_token_account.key != platform_data.vault_token_account {
+            msg!("wrong vault");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let system_clock = Clock::get()?;
+        let grace_over = platform_data.sale_end != 0
+            && Self::unix_timestamp(&system_clock)?
+                >= platform_data.sale_end.saturating_add(CLOSE_GRACE_PERIOD);
+        if platform_data.open_positions != 0 && !grace_over {
+            msg!("{} positions still open", platform_data.open_positions);
+            return Err(FarmError::IllegalAction.into());
+        }
+
+        let pda_prefix = "Private_selling";
+        let pda_seed = &[pda_prefix.as_bytes(), (platform_state_account.key).as_ref()];
+        let (pda, nonce) = Pubkey::find_program_address(pda_seed, program_id);
+        if pda != *pda_account.key {
+            msg!("wrong pda");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let pda_signer_seeds: &[&[u8]] = &[
+            pda_prefix.as_bytes(),
+            platform_state_account.key.as_ref(),
+            &[nonce],
+        ];
+
+        //the escrow can't be reached once the platform is gone, nothing may be left in it
+        let escrow_token_account = if platform_data.soft_cap != 0 {
+            let escrow_token_account = next_account_info(account_info_iter)?; //escrow pinned at init
+            if *escrow_token_account.key != platform_data.escrow_token_account {
+                msg!("wrong escrow");
+                return Err(ProgramError::InvalidAccountData);
+            }
+            let escrow_balance = TokenAccount::unpack(&escrow_token_account.try_borrow_data()?)?.amount;
+            if escrow_balance != 0 || platform_data.escrowed_fees != 0 {
+                msg!("escrow still holds {}", escrow_balance);
+                return Err(FarmError::IllegalAction.into());
+            }
+            Some(escrow_token_account)
+        } else {
+            None
+        };
+
+        let residual = TokenAccount::unpack(&pda_token_account.try_borrow_data()?)?.amount;
+        if residual != 0 {
+            let transfer_token = transfer(
+                token_program.key,
+                pda_token_account.key,
+                destination_token_account.key,
+                &pda,
+                &[],
+                residual,
+            )?;
+            invoke_signed(
+                &transfer_token,
+                &[
+                    pda_token_account.clone(),
+                    destination_token_account.clone(),
+                    pda_account.clone(),
+                    token_program.clone(),
+                ],
+                &[pda_signer_seeds],
+            )?;
+        }
+
+        for token_account in std::iter::once(pda_token_account).chain(escrow_token_account) {
+            let close_token_account = close_account(
+                token_program.key,
+                token_account.key,
+                owner_account.key,
+                &pda,
+                &[],
+            )?;
+            invoke_signed(
+                &close_token_account,
+                &[
+                    token_account.clone(),
+                    owner_account.clone(),
+                    pda_account.clone(),
+                    token_program.clone(),
+                ],
+                &[pda_signer_seeds],
+            )?;
+        }
+
+        let owner_lamports = owner_account.lamports();
+        **owner_account.lamports.borrow_mut() = owner_lamports
+            .checked_add(platform_state_account.lamports())
+            .ok_or(FarmError::NumericOverflow)?;
+        **platform_state_account.lamports.borrow_mut() = 0;
+        platform_state_account.try_borrow_mut_data()?.fill(0);
+        msg!("closed platform, swept {}", residual);
+
+        Ok(())
+    }
+
+    /// First step of an ownership change, nothing changes until `new_owner`
+    /// accepts. Proposing again replaces the earlier proposal.
+    pub fn process_propose_owner(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        new_owner: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        platform_data.pending_owner = new_owner;
+        msg!("pending owner {}", new_owner);
+
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Second step of an ownership change, signed by the proposed owner.
+    pub fn process_accept_owner(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let new_owner_account = next_account_info(account_info_iter)?; //pending owner, must sign
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+
+        if !new_owner_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if platform_data.pending_owner == Pubkey::default()
+            || platform_data.pending_owner != *new_owner_account.key
+        {
+            return Err(FarmError::Unauthorized.into());
+        }
+
+        platform_data.owner = platform_data.pending_owner;
+        platform_data.pending_owner = Pubkey::default();
+        msg!("new owner {}", platform_data.owner);
+
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Hands owner only instructions to an M-of-N admin set, or back to the
+    /// owner alone with an empty set. Needs the current authority to sign.
+    pub fn process_set_admins(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        threshold: u8,
+        admins: Vec<Pubkey>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner or admin, must sign
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        if admins.len() > MAX_ADMINS || threshold as usize > admins.len() {
+            return Err(FarmError::InvalidAdminSet.into());
+        }
+        if !admins.is_empty() && threshold == 0 {
+            return Err(FarmError::InvalidAdminSet.into());
+        }
+        let mut unique = admins.clone();
+        unique.sort();
+        unique.dedup();
+        if unique.len() != admins.len() {
+            return Err(FarmError::InvalidAdminSet.into());
+        }
+
+        platform_data.admin_threshold = threshold;
+        platform_data.admin_count = admins.len() as u8;
+        platform_data.admins = [Pubkey::default(); MAX_ADMINS];
+        platform_data.admins[..admins.len()].copy_from_slice(&admins);
+        msg!("{} of {} admins", threshold, admins.len());
+
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Points the fees at the token accounts of another wallet.
+    pub fn process_set_fee_recipient(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        fee_recipient: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        platform_data.fee_recipient = fee_recipient;
+        msg!("fee recipient {}", fee_recipient);
+
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Accepts `mint` next to the init payment mint at its own price, paid into
+    /// `treasury_token_account`. A mint already in the table gets the new
+    /// price and treasury.
+    pub fn process_add_payment_mint(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        token_price: u64,
+        token_price_den: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+        let mint_account = next_account_info(account_info_iter)?; //mint to accept e.g. USDT
+        let treasury_token_account = next_account_info(account_info_iter)?; //(treasory wallet) account of that mint
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        //the init payment mint is priced by UpdatePlatform
+        if *mint_account.key == platform_data.payment_mint {
+            return Err(FarmError::InvalidMint.into());
+        }
+        let mint = Mint::unpack(&mint_account.try_borrow_data()?)?;
+        if TokenAccount::unpack(&treasury_token_account.try_borrow_data()?)?.mint
+            != *mint_account.key
+        {
+            return Err(FarmError::InvalidMint.into());
+        }
+        let payment_terms = PaymentMint {
+            mint: *mint_account.key,
+            decimals: mint.decimals,
+            token_price,
+            token_price_den,
+            treasury: *treasury_token_account.key,
+        };
+        Self::check_price(&payment_terms)?;
+
+        let count = platform_data.payment_mint_count as usize;
+        match platform_data.payment_mints[..count]
+            .iter()
+            .position(|payment_mint| payment_mint.mint == payment_terms.mint)
+        {
+            Some(index) => platform_data.payment_mints[index] = payment_terms,
+            None => {
+                if count >= MAX_PAYMENT_MINTS {
+                    return Err(FarmError::PaymentMintTableFull.into());
+                }
+                platform_data.payment_mints[count] = payment_terms;
+                platform_data.payment_mint_count += 1;
+            }
+        }
+        msg!("payment mint {} price {} / {}", mint_account.key, token_price, token_price_den);
+
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Fees go to a token account of `fee_recipient` in the mint of the
+    /// account they are paid from.
+    pub fn check_fee_account(
+        platform_data: &PlatForm,
+        fee_token_account: &AccountInfo,
+        payment_token_account: &AccountInfo,
+    ) -> ProgramResult {
+        let fee_account = TokenAccount::unpack(&fee_token_account.try_borrow_data()?)?;
+        if fee_account.owner != platform_data.fee_recipient {
+            return Err(FarmError::InvalidTokenAccountOwner.into());
+        }
+        let payment = TokenAccount::unpack(&payment_token_account.try_borrow_data()?)?;
+        if fee_account.mint != payment.mint {
+            return Err(FarmError::InvalidMint.into());
+        }
+        Ok(())
+    }
+
+    /// The escrow is the account pinned at init, of the same mint as the payment
+    /// account it is paired with.
+    pub fn check_escrow(
+        platform_data: &PlatForm,
+        escrow_token_account: &AccountInfo,
+        payment_token_account: &AccountInfo,
+        pda: &Pubkey,
+    ) -> ProgramResult {
+        if *escrow_token_account.key != platform_data.escrow_token_account {
+            msg!("wrong escrow");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let escrow = TokenAccount::unpack(&escrow_token_account.try_borrow_data()?)?;
+        if escrow.owner != *pda {
+            return Err(FarmError::InvalidTokenAccountOwner.into());
+        }
+        let payment = TokenAccount::unpack(&payment_token_account.try_borrow_data()?)?;
+        if escrow.mint != payment.mint {
+            return Err(FarmError::InvalidMint.into());
+        }
+        Ok(())
+    }
+
+    /// Circuit breaker: `paused` stops `PrivateSell`, `claims_paused` also stops `Claim`.
+    pub fn process_set_paused(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        paused: bool,
+        claims_paused: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let platform_state_account = next_account_info(account_info_iter)?;
+        let owner_account = next_account_info(account_info_iter)?; //platform owner, must sign
+
+        if platform_state_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut platform_data = PlatForm::unpack(&platform_state_account.try_borrow_data()?)?;
+        Self::check_owner(&platform_data, owner_account, accounts)?;
+
+        platform_data.paused = paused;
+        platform_data.claims_paused = claims_paused;
+        msg!("paused {} claims paused {}", paused, claims_paused);
+
+        PlatForm::pack(
+            platform_data,
+            &mut platform_state_account.try_borrow_mut_data()?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Walks `proof` from `leaf` up to `root`. Each pair is hashed in sorted
+    /// order, so the proof doesn't need to say on which side a sibling is.
+    pub fn verify_merkle_proof(proof: &[[u8; 32]], root: &[u8; 32], leaf: [u8; 32]) -> bool {
+        let computed = proof.iter().fold(leaf, |node, sibling| {
+            if node <= *sibling {
+                hashv(&[&node, sibling]).0
+            } else {
+                hashv(&[sibling, &node]).0
+            }
+        });
+        computed == *root
+    }
+
+    /// `sale_end` of 0 leaves the sale open until the vault runs dry, otherwise
+    /// it has to come after `sale_start`. Soft cap sales always need an end.
+    pub fn set_sale_window(platform_data: &mut PlatForm, sale_start: u64, sale_end: u64) -> ProgramResult {
+        if sale_end != 0 && sale_end <= sale_start {
+            return Err(FarmError::InvalidSaleWindow.into());
+        }
+        //refunds open when the sale ends, so a soft cap needs an end
+        if platform_data.soft_cap != 0 && sale_end == 0 {
+            return Err(FarmError::InvalidSaleWindow.into());
+        }
+        platform_data.sale_start = sale_start;
+        platform_data.sale_end = sale_end;
+        Ok(())
+    }
+
+    /// Admin instructions must be signed by `PlatForm.owner`, or once an admin
+    /// set is configured by `admin_threshold` of its keys, `owner_account` being one.
+    pub fn check_owner(
+        platform_data: &PlatForm,
+        owner_account: &AccountInfo,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        if !owner_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if platform_data.admin_threshold == 0 {
+            if platform_data.owner != *owner_account.key {
+                return Err(FarmError::Unauthorized.into());
+            }
+            return Ok(());
+        }
+
+        //any account of the instruction can carry an admin signature, each admin counts once
+        let admins = platform_data.admins();
+        if !admins.contains(owner_account.key) {
+            return Err(FarmError::Unauthorized.into());
+        }
+        let mut signers: Vec<&Pubkey> = accounts
+            .iter()
+            .filter(|account| account.is_signer && admins.contains(account.key))
+            .map(|account| account.key)
+            .collect();
+        signers.sort();
+        signers.dedup();
+        if signers.len() < platform_data.admin_threshold as usize {
+            msg!("{} of {} admin signatures", signers.len(), platform_data.admin_threshold);
+            return Err(FarmError::Unauthorized.into());
+        }
+        Ok(())
+    }
+
+    /// Everything unlocked so far over all purchases, and the part of it the
+    /// user didn't receive yet (init stages and earlier claims are taken off).
+    pub fn claimable_amount(
+        platform: &PlatForm,
+        user_data: &UserState,
+        now: u64,
+    ) -> Result<(u64, u64), ProgramError> {
+        let unlocked_amount = Self::position_unlocked_amount(platform, user_data, now)?;
+        let released_amount = user_data
+            .buying_amount
+            .checked_sub(user_data.vesting_amount)
+            .ok_or(FarmError::NumericOverflow)?;
+        Ok((unlocked_amount, unlocked_amount.saturating_sub(released_amount)))
+    }
+
+    /// Sum of `unlocked_amount` over every purchase of a position at `now`.
+    pub fn position_unlocked_amount(
+        platform_data: &PlatForm,
+        user_data: &UserState,
+        now: u64,
+    ) -> Result<u64, ProgramError> {
+        user_data
+            .purchases()
+            .iter()
+            .try_fold(0u64, |total, purchase| {
+                //the clock can't be behind a purchase it stamped
+                let user_duration = now
+                    .checked_sub(purchase.buying_timestamp)
+                    .ok_or(FarmError::ClockSkew)?;
+                let unlocked =
+                    Self::unlocked_amount(platform_data, purchase.buying_amount, user_duration)?;
+                total
+                    .checked_add(unlocked)
+                    .ok_or_else(|| FarmError::NumericOverflow.into())
+            })
+    }
+
+    /// Total amount of a purchase that is unlocked `user_duration` seconds after buying,
+    /// including the init stage paid out by `process_sell`.
+    ///
+    /// Nothing beyond the init stage unlocks before the `cliff` has passed. After
+    /// that, staged mode unlocks every tranche whose offset has passed; once the
+    /// last tranche is reached the whole `buying_amount` is unlocked so rounding
+    /// dust is not left behind. Linear mode vests `buying_amount - init_stage`
+    /// evenly over `vesting_period`, counted from buying, so whatever accrued
+    /// during the cliff is released in one lump when it ends.
+    pub fn unlocked_amount(
+        platform_data: &PlatForm,
+        buying_amount: u64,
+        user_duration: u64,
+    ) -> Result<u64, ProgramError> {
+        let init_stage_amount = Self::mul_div(buying_amount, platform_data.init_stage, 100)?;
+        if user_duration < platform_data.cliff {
+            return Ok(init_stage_amount);
+        }
+
+        match platform_data.vesting_mode {
+            VestingMode::Staged => {
+                let schedule = platform_data.schedule();
+                if schedule.iter().all(|tranche| tranche.offset <= user_duration) {
+                    return Ok(buying_amount);
+                }
+                let unlocked_percentage = schedule
+                    .iter()
+                    .filter(|tranche| tranche.offset <= user_duration)
+                    .try_fold(platform_data.init_stage, |total, tranche| {
+                        total.checked_add(tranche.percentage)
+                    })
+                    .ok_or(FarmError::NumericOverflow)?;
+                Self::mul_div(buying_amount, unlocked_percentage, 100)
+            }
+            VestingMode::Linear => {
+                if user_duration >= platform_data.vesting_period {
+                    return Ok(buying_amount);
+                }
+                let locked_amount = buying_amount
+                    .checked_sub(init_stage_amount)
+                    .ok_or(FarmError::NumericOverflow)?;
+                let vested_amount =
+                    Self::mul_div(locked_amount, user_duration, platform_data.vesting_period)?;
+                init_stage_amount
+                    .checked_add(vested_amount)
+                    .ok_or_else(|| FarmError::NumericOverflow.into())
+            }
+        }
+    }
+
+    /// Tokens delivered for `amount`, the part of them released at once, what
+    /// the buyer pays and the fee withheld in vesting tokens. Depending on
+    /// `fee_mode` the fee is either on top of the payment or taken off the
+    /// tokens bought. With a soft cap nothing is released at once, the init
+    /// stage stays locked until the cap is met.
+    pub fn sale_amounts(
+        platform_data: &PlatForm,
+        payment_terms: &PaymentMint,
+        amount: u64,
+    ) -> Result<(u64, u64, u64, u64), ProgramError> {
+        let bought_amount = Self::token_amount(platform_data, payment_terms, amount)?;
+        //fees round down, in favour of the buyer
+        let (token_amount, paid_amount, fee_token_amount) = match platform_data.fee_mode {
+            FeeMode::PaymentToken => {
+                let paid_amount = amount
+                    .checked_add(Self::mul_div(amount, platform_data.fee_bps, BPS_DENOMINATOR)?)
+                    .ok_or(FarmError::NumericOverflow)?;
+                (bought_amount, paid_amount, 0)
+            }
+            FeeMode::VestingToken => {
+                let fee_token_amount =
+                    Self::mul_div(bought_amount, platform_data.fee_bps, BPS_DENOMINATOR)?;
+                let token_amount = bought_amount
+                    .checked_sub(fee_token_amount)
+                    .ok_or(FarmError::NumericOverflow)?;
+                (token_amount, amount, fee_token_amount)
+            }
+        };
+        let init_stage_amount = if platform_data.soft_cap == 0 {
+            Self::mul_div(token_amount, platform_data.init_stage, 100)?
+        } else {
+            0
+        };
+        Ok((token_amount, init_stage_amount, paid_amount, fee_token_amount))
+    }
+
+    /// Base units of the vault mint bought for `amount` base units of the
+    /// payment mint, at the price set for that mint:
+    ///
+    /// `amount * token_price * 10^vesting_decimals / (token_price_den * 10^payment_decimals)`
+    ///
+    /// The division rounds down, so the buyer never gets more than paid for and
+    /// the dust stays in the vault.
+    pub fn token_amount(
+        platform_data: &PlatForm,
+        payment_terms: &PaymentMint,
+        amount: u64,
+    ) -> Result<u64, ProgramError> {
+        let numerator = 10u128
+            .checked_pow(platform_data.vesting_decimals as u32)
+            .and_then(|scale| scale.checked_mul(payment_terms.token_price as u128))
+            .ok_or(FarmError::NumericOverflow)?;
+        let denominator = 10u128
+            .checked_pow(payment_terms.decimals as u32)
+            .and_then(|scale| scale.checked_mul(payment_terms.token_price_den as u128))
+            .ok_or(FarmError::NumericOverflow)?;
+        let token_amount = (amount as u128)
+            .checked_mul(numerator)
+            .and_then(|product| product.checked_div(denominator))
+            .ok_or(FarmError::NumericOverflow)?;
+        u64::try_from(token_amount).map_err(|_| FarmError::NumericOverflow.into())
+    }
+
+    /// The fee can't be larger than the payment itself. Refunds can't take
+    /// back vesting tokens from the fee vault, so soft cap sales charge the fee
+    /// in the payment token.
+    pub fn check_fee(platform_data: &PlatForm) -> ProgramResult {
+        if platform_data.fee_bps > BPS_DENOMINATOR {
+            return Err(FarmError::InvalidFee.into());
+        }
+        if platform_data.soft_cap != 0 && platform_data.fee_mode == FeeMode::VestingToken {
+            return Err(FarmError::InvalidFee.into());
+        }
+        Ok(())
+    }
+
+    /// Both sides of the price must be set.
+    pub fn check_price(payment_terms: &PaymentMint) -> ProgramResult {
+        if payment_terms.token_price == 0 || payment_terms.token_price_den == 0 {
+            return Err(FarmError::InvalidPrice.into());
+        }
+        Ok(())
+    }
+
+    /// `value * numerator / denominator` rounded down, through a `u128` so only
+    /// a result above `u64::MAX` (or a zero denominator) is an error.
+    pub fn mul_div(value: u64, numerator: u64, denominator: u64) -> Result<u64, ProgramError> {
+        let result = (value as u128)
+            .checked_mul(numerator as u128)
+            .and_then(|product| product.checked_div(denominator as u128))
+            .ok_or(FarmError::NumericOverflow)?;
+        u64::try_from(result).map_err(|_| FarmError::NumericOverflow.into())
+    }
+
+    /// The cluster clock as unsigned seconds, a clock before 1970 is skew.
+    pub fn unix_timestamp(clock: &Clock) -> Result<u64, ProgramError> {
+        u64::try_from(clock.unix_timestamp).map_err(|_| FarmError::ClockSkew.into())
+    }
+
+    /// Stores `schedule` in the platform state and validates it against the
+    /// rest of the vesting settings.
+    pub fn set_schedule(platform_data: &mut PlatForm, schedule: &[VestingTranche]) -> ProgramResult {
+        if schedule.len() > MAX_VESTING_TRANCHES {
+            return Err(FarmError::InvalidSchedule.into());
+        }
+        platform_data.schedule = [VestingTranche::default(); MAX_VESTING_TRANCHES];
+        platform_data.schedule[..schedule.len()].copy_from_slice(schedule);
+        platform_data.schedule_len = schedule.len() as u8;
+        Self::validate_schedule(platform_data)
+    }
+
+    /// A staged schedule must fit in the platform state, unlock in time order
+    /// and, together with the init stage, add up to exactly 100%.
+    pub fn validate_schedule(platform_data: &PlatForm) -> ProgramResult {
+        if platform_data.init_stage > 100 {
+            return Err(FarmError::InvalidSchedule.into());
+        }
+        if platform_data.vesting_mode == VestingMode::Linear {
+            return Ok(());
+        }
+
+        let schedule = platform_data.schedule();
+        if schedule.is_empty()
+            || schedule.windows(2).any(|pair| pair[0].offset > pair[1].offset)
+        {
+            return Err(FarmError::InvalidSchedule.into());
+        }
+        let total_percentage = schedule
+            .iter()
+            .try_fold(platform_data.init_stage, |total, tranche| {
+                total.checked_add(tranche.percentage)
+            })
+            .ok_or(FarmError::NumericOverflow)?;
+        if total_percentage != 100 {
+            return Err(FarmError::InvalidSchedule.into());
+        }
+        Ok(())
+    }
 }
@@ -4,6 +4,120 @@ use solana_program::{
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
 };
+
+/// Longest staged schedule a platform can hold, e.g. 24 monthly tranches.
+pub const MAX_VESTING_TRANCHES: usize = 24;
+
+/// `fee_bps` of 10_000 is a fee as large as the payment.
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Payment mints a platform accepts next to the one set at init.
+pub const MAX_PAYMENT_MINTS: usize = 4;
+
+/// Largest admin set a platform can hold.
+pub const MAX_ADMINS: usize = 10;
+
+/// How long after `sale_end` the owner can close a platform that still has open positions (2 years).
+pub const CLOSE_GRACE_PERIOD: u64 = 2 * 365 * 24 * 60 * 60;
+
+/// How the locked part of a purchase is released after the init stage.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VestingMode {
+    /// Steps through the platform `schedule` tranches.
+    Staged = 0,
+    /// Grows every second from `buying_timestamp` to `buying_timestamp + vesting_period`.
+    Linear = 1,
+}
+impl VestingMode {
+    pub fn from_u8(mode: u8) -> Option<Self> {
+        match mode {
+            0 => Some(VestingMode::Staged),
+            1 => Some(VestingMode::Linear),
+            _ => None,
+        }
+    }
+}
+
+/// What the platform fee is charged in.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FeeMode {
+    /// Paid by the buyer on top of the payment.
+    PaymentToken = 0,
+    /// Withheld from the tokens bought and sent to the fee vault.
+    VestingToken = 1,
+}
+impl FeeMode {
+    pub fn from_u8(mode: u8) -> Option<Self> {
+        match mode {
+            0 => Some(FeeMode::PaymentToken),
+            1 => Some(FeeMode::VestingToken),
+            _ => None,
+        }
+    }
+}
+
+/// A mint `PrivateSell` accepts, with its own price and treasury token account.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct PaymentMint {
+    pub mint: Pubkey,
+    pub decimals: u8,
+    pub token_price: u64,
+    pub token_price_den: u64,
+    pub treasury: Pubkey,
+}
+impl PaymentMint {
+    pub const LEN: usize = 81;
+
+    pub fn unpack_from_slice(src: &[u8; PaymentMint::LEN]) -> Self {
+        let (mint, decimals, token_price, token_price_den, treasury) =
+            array_refs![src, 32, 1, 8, 8, 32];
+        PaymentMint {
+            mint: Pubkey::new_from_array(*mint),
+            decimals: decimals[0],
+            token_price: u64::from_le_bytes(*token_price),
+            token_price_den: u64::from_le_bytes(*token_price_den),
+            treasury: Pubkey::new_from_array(*treasury),
+        }
+    }
+
+    pub fn pack_into_slice(&self, dst: &mut [u8; PaymentMint::LEN]) {
+        let (mint_dst, decimals_dst, token_price_dst, token_price_den_dst, treasury_dst) =
+            mut_array_refs![dst, 32, 1, 8, 8, 32];
+        mint_dst.copy_from_slice(self.mint.as_ref());
+        decimals_dst[0] = self.decimals;
+        *token_price_dst = self.token_price.to_le_bytes();
+        *token_price_den_dst = self.token_price_den.to_le_bytes();
+        treasury_dst.copy_from_slice(self.treasury.as_ref());
+    }
+}
+
+/// One step of a staged schedule: `percentage` of the purchase unlocks
+/// `offset` seconds after `buying_timestamp`.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct VestingTranche {
+    pub offset: u64,
+    pub percentage: u64,
+}
+impl VestingTranche {
+    pub const LEN: usize = 16;
+
+    pub fn unpack_from_slice(src: &[u8; VestingTranche::LEN]) -> Self {
+        let (offset, percentage) = array_refs![src, 8, 8];
+        VestingTranche {
+            offset: u64::from_le_bytes(*offset),
+            percentage: u64::from_le_bytes(*percentage),
+        }
+    }
+
+    pub fn pack_into_slice(&self, dst: &mut [u8; VestingTranche::LEN]) {
+        let (offset_dst, percentage_dst) = mut_array_refs![dst, 8, 8];
+        *offset_dst = self.offset.to_le_bytes();
+        *percentage_dst = self.percentage.to_le_bytes();
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub struct PlatForm {
     pub is_initialized: bool,
@@ -13,14 +127,96 @@ pub struct PlatForm {
     pub token_price: u64,
 
     pub init_stage: u64,
-    pub stage_1: u64,
-    pub stage_2: u64,
-    pub stage_3: u64,
-    pub stage_4: u64,
-    pub platform_fess: u64,
+    pub fee_bps: u64, //fee on top of the payment in basis points
+    pub vesting_mode: VestingMode,
+    pub cliff: u64,
+    pub schedule_len: u8,
+    pub schedule: [VestingTranche; MAX_VESTING_TRANCHES],
+    pub schedule_locked: bool,
+    pub sale_start: u64,
+    pub sale_end: u64,
+    pub hard_cap: u64, //most tokens the sale can sell
+    pub total_sold: u64, //tokens sold so far, vested or not
+    pub min_purchase: u64,
+    pub max_purchase_per_wallet: u64,
+    pub merkle_root: [u8; 32], //allowlist of (buyer, allocation) leaves, all zero => anyone can buy
+    pub paused: bool, //stops PrivateSell
+    pub claims_paused: bool, //stops Claim
+    pub vault_token_account: Pubkey, //PDA token account holding the unsold tokens
+    pub soft_cap: u64, //least tokens to sell before the proceeds leave the escrow, 0 => no escrow
+    pub revocable: bool, //new positions can be revoked by the owner
+    pub transfer_requires_approval: bool, //TransferPosition needs the owner signature too
+    pub open_positions: u64, //positions still vesting, ClosePlatform waits for 0
+    pub pending_owner: Pubkey, //proposed by the owner, becomes owner with AcceptOwner
+    pub admin_threshold: u8, //admin signatures needed for owner only instructions, 0 => the owner alone
+    pub admin_count: u8,
+    pub admins: [Pubkey; MAX_ADMINS],
+    pub token_price_den: u64, //whole tokens per whole payment token = token_price / token_price_den
+    pub payment_mint: Pubkey, //mint buyers pay with
+    pub payment_decimals: u8,
+    pub vesting_decimals: u8, //decimals of the vault mint
+    pub fee_recipient: Pubkey, //wallet owning the token accounts fees are paid to
+    pub escrowed_fees: u64, //fees held in the soft cap escrow, paid out by WithdrawProceeds
+    pub fee_mode: FeeMode,
+    pub treasury: Pubkey, //payment_mint token account the payments go to
+    pub payment_mint_count: u8,
+    pub payment_mints: [PaymentMint; MAX_PAYMENT_MINTS], //further accepted mints, added by AddPaymentMint
+    pub escrow_token_account: Pubkey, //PDA owned payment_mint account holding the payments while the soft cap is pending
+    pub soft_cap_reached: bool, //set by the purchase reaching the soft cap, stays set when Revoke lowers total_sold again
 
-    
+}
+impl PlatForm {
+    /// The tranches actually in use, in unlock order.
+    pub fn schedule(&self) -> &[VestingTranche] {
+        &self.schedule[..self.schedule_len as usize]
+    }
+
+    /// Whether `PrivateSell` accepts purchases at `now`.
+    pub fn is_sale_open(&self, now: u64) -> bool {
+        now >= self.sale_start && !self.is_sale_ended(now)
+    }
+
+    /// Whether the sale window has closed for good at `now`; a `sale_end` of 0 never closes.
+    pub fn is_sale_ended(&self, now: u64) -> bool {
+        self.sale_end != 0 && now >= self.sale_end
+    }
+
+    /// Whether the owner can take the proceeds and buyers can claim; always true without a soft cap.
+    pub fn is_soft_cap_reached(&self) -> bool {
+        self.soft_cap_reached || self.total_sold >= self.soft_cap
+    }
+
+    /// The admin keys actually in use.
+    pub fn admins(&self) -> &[Pubkey] {
+        &self.admins[..self.admin_count as usize]
+    }
 
+    /// Price and treasury of the mint set at init.
+    pub fn primary_payment_terms(&self) -> PaymentMint {
+        PaymentMint {
+            mint: self.payment_mint,
+            decimals: self.payment_decimals,
+            token_price: self.token_price,
+            token_price_den: self.token_price_den,
+            treasury: self.treasury,
+        }
+    }
+
+    /// Price and treasury of `mint`, `None` when the platform doesn't accept it.
+    pub fn payment_terms(&self, mint: &Pubkey) -> Option<PaymentMint> {
+        if *mint == self.payment_mint {
+            return Some(self.primary_payment_terms());
+        }
+        self.payment_mints[..self.payment_mint_count as usize]
+            .iter()
+            .find(|payment_mint| payment_mint.mint == *mint)
+            .copied()
+    }
+
+    /// Tokens that can still be sold before the hard cap is reached.
+    pub fn remaining_allocation(&self) -> u64 {
+        self.hard_cap.saturating_sub(self.total_sold)
+    }
 }
 impl Sealed for PlatForm {}
 impl IsInitialized for PlatForm {
@@ -29,7 +225,7 @@ impl IsInitialized for PlatForm {
     }
 }
 impl Pack for PlatForm {
-    const LEN: usize = 105;
+    const LEN: usize = 1427;
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
         let src = array_ref![src, 0, PlatForm::LEN];
         let (is_initialized,
@@ -38,18 +234,93 @@ impl Pack for PlatForm {
             vesting_period,
             token_price,
             init_stage,
-            stage_1,
-            stage_2,
-            stage_3,
-            stage_4,
-            platform_fess,
+            fee_bps,
+            vesting_mode,
+            cliff,
+            schedule_len,
+            schedule_src,
+            schedule_locked,
+            sale_start,
+            sale_end,
+            hard_cap,
+            total_sold,
+            min_purchase,
+            max_purchase_per_wallet,
+            merkle_root,
+            paused,
+            claims_paused,
+            vault_token_account,
+            soft_cap,
+            revocable,
+            transfer_requires_approval,
+            open_positions,
+            pending_owner,
+            admin_threshold,
+            admin_count,
+            admins_src,
+            token_price_den,
+            payment_mint,
+            payment_decimals,
+            vesting_decimals,
+            fee_recipient,
+            escrowed_fees,
+            fee_mode,
+            treasury,
+            payment_mint_count,
+            payment_mints_src,
+            escrow_token_account,
+            soft_cap_reached,
 
-            ) = array_refs![src, 1, 32, 8,8,8,8,8,8,8,8,8];
+            ) = array_refs![src, 1, 32, 8,8,8,8,8,1,8,1,VestingTranche::LEN * MAX_VESTING_TRANCHES,1,8,8,8,8,8,8,32,1,1,32,8,1,1,8,32,1,1,32 * MAX_ADMINS,8,32,1,1,32,8,1,32,1,PaymentMint::LEN * MAX_PAYMENT_MINTS,32,1];
+        if payment_mint_count[0] as usize > MAX_PAYMENT_MINTS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut payment_mints = [PaymentMint::default(); MAX_PAYMENT_MINTS];
+        for (payment_mint, payment_mint_src) in payment_mints
+            .iter_mut()
+            .zip(payment_mints_src.chunks_exact(PaymentMint::LEN))
+        {
+            *payment_mint = PaymentMint::unpack_from_slice(array_ref![payment_mint_src, 0, PaymentMint::LEN]);
+        }
+        if admin_count[0] as usize > MAX_ADMINS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut admins = [Pubkey::default(); MAX_ADMINS];
+        for (admin, admin_src) in admins.iter_mut().zip(admins_src.chunks_exact(32)) {
+            *admin = Pubkey::new_from_array(*array_ref![admin_src, 0, 32]);
+        }
         let is_initialized = match is_initialized {
             [0] => false,
             [1] => true,
             _ => return Err(ProgramError::InvalidAccountData),
         };
+        let schedule_locked = match schedule_locked {
+            [0] => false,
+            [1] => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let paused = match paused {
+            [0] => false,
+            [1] => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let claims_paused = match claims_paused {
+            [0] => false,
+            [1] => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let vesting_mode =
+            VestingMode::from_u8(vesting_mode[0]).ok_or(ProgramError::InvalidAccountData)?;
+        if schedule_len[0] as usize > MAX_VESTING_TRANCHES {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut schedule = [VestingTranche::default(); MAX_VESTING_TRANCHES];
+        for (tranche, tranche_src) in schedule
+            .iter_mut()
+            .zip(schedule_src.chunks_exact(VestingTranche::LEN))
+        {
+            *tranche = VestingTranche::unpack_from_slice(array_ref![tranche_src, 0, VestingTranche::LEN]);
+        }
         Ok(PlatForm {
             is_initialized,
             owner: Pubkey::new_from_array(*owner),
@@ -58,21 +329,68 @@ impl Pack for PlatForm {
             token_price: u64::from_le_bytes(*token_price),
 
             init_stage: u64::from_le_bytes(*init_stage),
-            stage_1: u64::from_le_bytes(*stage_1),
-            stage_2: u64::from_le_bytes(*stage_2),
-            stage_3: u64::from_le_bytes(*stage_3),
-            stage_4: u64::from_le_bytes(*stage_4),
-            platform_fess: u64::from_le_bytes(*platform_fess),
-
-
-
-
+            fee_bps: u64::from_le_bytes(*fee_bps),
+            vesting_mode,
+            cliff: u64::from_le_bytes(*cliff),
+            schedule_len: schedule_len[0],
+            schedule,
+            schedule_locked,
+            sale_start: u64::from_le_bytes(*sale_start),
+            sale_end: u64::from_le_bytes(*sale_end),
+            hard_cap: u64::from_le_bytes(*hard_cap),
+            total_sold: u64::from_le_bytes(*total_sold),
+            min_purchase: u64::from_le_bytes(*min_purchase),
+            max_purchase_per_wallet: u64::from_le_bytes(*max_purchase_per_wallet),
+            merkle_root: *merkle_root,
+            paused,
+            claims_paused,
+            vault_token_account: Pubkey::new_from_array(*vault_token_account),
+            soft_cap: u64::from_le_bytes(*soft_cap),
+            revocable: match revocable {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            transfer_requires_approval: match transfer_requires_approval {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            open_positions: u64::from_le_bytes(*open_positions),
+            pending_owner: Pubkey::new_from_array(*pending_owner),
+            admin_threshold: admin_threshold[0],
+            admin_count: admin_count[0],
+            admins,
+            token_price_den: u64::from_le_bytes(*token_price_den),
+            payment_mint: Pubkey::new_from_array(*payment_mint),
+            payment_decimals: payment_decimals[0],
+            vesting_decimals: vesting_decimals[0],
+            fee_recipient: Pubkey::new_from_array(*fee_recipient),
+            escrowed_fees: u64::from_le_bytes(*escrowed_fees),
+            fee_mode: FeeMode::from_u8(fee_mode[0]).ok_or(ProgramError::InvalidAccountData)?,
+            treasury: Pubkey::new_from_array(*treasury),
+            payment_mint_count: payment_mint_count[0],
+            payment_mints,
+            escrow_token_account: Pubkey::new_from_array(*escrow_token_account),
+            soft_cap_reached: match soft_cap_reached {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
         })
     }
     fn pack_into_slice(&self, dst: &mut [u8]) {
         let dst = array_mut_ref![dst, 0, PlatForm::LEN];
         let (is_initialized_dst, owner_dst, vesting_per_dst,vesting_period_dst,
-            token_price_dst,init_stage_dst,stage_1_dst,stage_2_dst,stage_3_dst,stage_4_dst,platform_fess_dst) = mut_array_refs![dst, 1, 32, 8,8,8,8,8,8,8,8,8];
+            token_price_dst,init_stage_dst,fee_bps_dst,vesting_mode_dst,cliff_dst,schedule_len_dst,schedule_dst,schedule_locked_dst,
+            sale_start_dst,sale_end_dst,hard_cap_dst,total_sold_dst,
+            min_purchase_dst,max_purchase_per_wallet_dst,merkle_root_dst,
+            paused_dst,claims_paused_dst,vault_token_account_dst,soft_cap_dst,revocable_dst,transfer_requires_approval_dst,open_positions_dst,pending_owner_dst,
+            admin_threshold_dst,admin_count_dst,admins_dst,
+            token_price_den_dst,payment_mint_dst,payment_decimals_dst,vesting_decimals_dst,
+            fee_recipient_dst,escrowed_fees_dst,fee_mode_dst,
+            treasury_dst,payment_mint_count_dst,payment_mints_dst,escrow_token_account_dst,soft_cap_reached_dst) =
+            mut_array_refs![dst, 1, 32, 8,8,8,8,8,1,8,1,VestingTranche::LEN * MAX_VESTING_TRANCHES,1,8,8,8,8,8,8,32,1,1,32,8,1,1,8,32,1,1,32 * MAX_ADMINS,8,32,1,1,32,8,1,32,1,PaymentMint::LEN * MAX_PAYMENT_MINTS,32,1];
         let PlatForm {
             is_initialized,
             owner,
@@ -80,11 +398,42 @@ impl Pack for PlatForm {
             vesting_period,
             token_price,
             init_stage,
-            stage_1,
-            stage_2,
-            stage_3,
-            stage_4,
-            platform_fess
+            fee_bps,
+            vesting_mode,
+            cliff,
+            schedule_len,
+            schedule,
+            schedule_locked,
+            sale_start,
+            sale_end,
+            hard_cap,
+            total_sold,
+            min_purchase,
+            max_purchase_per_wallet,
+            merkle_root,
+            paused,
+            claims_paused,
+            vault_token_account,
+            soft_cap,
+            revocable,
+            transfer_requires_approval,
+            open_positions,
+            pending_owner,
+            admin_threshold,
+            admin_count,
+            admins,
+            token_price_den,
+            payment_mint,
+            payment_decimals,
+            vesting_decimals,
+            fee_recipient,
+            escrowed_fees,
+            fee_mode,
+            treasury,
+            payment_mint_count,
+            payment_mints,
+            escrow_token_account,
+            soft_cap_reached,
         } = self;
         is_initialized_dst[0] = *is_initialized as u8;
         owner_dst.copy_from_slice(owner.as_ref());
@@ -93,26 +442,114 @@ impl Pack for PlatForm {
         *token_price_dst = token_price.to_le_bytes();
 
         *init_stage_dst = init_stage.to_le_bytes();
-        *stage_1_dst = stage_1.to_le_bytes();
-        *stage_2_dst = stage_2.to_le_bytes();
-        *stage_3_dst = stage_3.to_le_bytes();
-        *stage_4_dst = stage_4.to_le_bytes();
-        *platform_fess_dst = platform_fess.to_le_bytes();
+        *fee_bps_dst = fee_bps.to_le_bytes();
+        vesting_mode_dst[0] = *vesting_mode as u8;
+        *cliff_dst = cliff.to_le_bytes();
+        schedule_len_dst[0] = *schedule_len;
+        for (tranche, tranche_dst) in schedule
+            .iter()
+            .zip(schedule_dst.chunks_exact_mut(VestingTranche::LEN))
+        {
+            tranche.pack_into_slice(array_mut_ref![tranche_dst, 0, VestingTranche::LEN]);
+        }
+        schedule_locked_dst[0] = *schedule_locked as u8;
+        *sale_start_dst = sale_start.to_le_bytes();
+        *sale_end_dst = sale_end.to_le_bytes();
+        *hard_cap_dst = hard_cap.to_le_bytes();
+        *total_sold_dst = total_sold.to_le_bytes();
+        *min_purchase_dst = min_purchase.to_le_bytes();
+        *max_purchase_per_wallet_dst = max_purchase_per_wallet.to_le_bytes();
+        merkle_root_dst.copy_from_slice(merkle_root);
+        paused_dst[0] = *paused as u8;
+        claims_paused_dst[0] = *claims_paused as u8;
+        vault_token_account_dst.copy_from_slice(vault_token_account.as_ref());
+        *soft_cap_dst = soft_cap.to_le_bytes();
+        revocable_dst[0] = *revocable as u8;
+        transfer_requires_approval_dst[0] = *transfer_requires_approval as u8;
+        *open_positions_dst = open_positions.to_le_bytes();
+        pending_owner_dst.copy_from_slice(pending_owner.as_ref());
+        admin_threshold_dst[0] = *admin_threshold;
+        admin_count_dst[0] = *admin_count;
+        for (admin, admin_dst) in admins.iter().zip(admins_dst.chunks_exact_mut(32)) {
+            admin_dst.copy_from_slice(admin.as_ref());
+        }
+        *token_price_den_dst = token_price_den.to_le_bytes();
+        payment_mint_dst.copy_from_slice(payment_mint.as_ref());
+        payment_decimals_dst[0] = *payment_decimals;
+        vesting_decimals_dst[0] = *vesting_decimals;
+        fee_recipient_dst.copy_from_slice(fee_recipient.as_ref());
+        *escrowed_fees_dst = escrowed_fees.to_le_bytes();
+        fee_mode_dst[0] = *fee_mode as u8;
+        treasury_dst.copy_from_slice(treasury.as_ref());
+        payment_mint_count_dst[0] = *payment_mint_count;
+        for (payment_mint, payment_mint_dst) in payment_mints
+            .iter()
+            .zip(payment_mints_dst.chunks_exact_mut(PaymentMint::LEN))
+        {
+            payment_mint.pack_into_slice(array_mut_ref![payment_mint_dst, 0, PaymentMint::LEN]);
+        }
+        escrow_token_account_dst.copy_from_slice(escrow_token_account.as_ref());
+        soft_cap_reached_dst[0] = *soft_cap_reached as u8;
 
 
     }
 }
 
+/// One `PrivateSell` of a wallet, vesting on its own from `buying_timestamp`.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct Purchase {
+    pub buying_timestamp: u64,
+    pub buying_amount: u64,
+    pub paid_amount: u64, //payment plus fees, given back by Refund
+    pub fee_amount: u64, //fee part of paid_amount, Refund takes it off the escrowed fees
+}
+impl Purchase {
+    pub const LEN: usize = 32;
+
+    pub fn unpack_from_slice(src: &[u8; Purchase::LEN]) -> Self {
+        let (buying_timestamp, buying_amount, paid_amount, fee_amount) =
+            array_refs![src, 8, 8, 8, 8];
+        Purchase {
+            buying_timestamp: u64::from_le_bytes(*buying_timestamp),
+            buying_amount: u64::from_le_bytes(*buying_amount),
+            paid_amount: u64::from_le_bytes(*paid_amount),
+            fee_amount: u64::from_le_bytes(*fee_amount),
+        }
+    }
+
+    pub fn pack_into_slice(&self, dst: &mut [u8; Purchase::LEN]) {
+        let (buying_timestamp_dst, buying_amount_dst, paid_amount_dst, fee_amount_dst) =
+            mut_array_refs![dst, 8, 8, 8, 8];
+        *buying_timestamp_dst = self.buying_timestamp.to_le_bytes();
+        *buying_amount_dst = self.buying_amount.to_le_bytes();
+        *paid_amount_dst = self.paid_amount.to_le_bytes();
+        *fee_amount_dst = self.fee_amount.to_le_bytes();
+    }
+}
+
+/// Most purchases a single wallet can make on one platform.
+pub const MAX_PURCHASES: usize = 16;
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub struct UserState {
     pub is_initialized: bool,
     pub user: Pubkey,
-    pub buying_timestamp: u64,
-    pub vesting_amount: u64,
-    pub buying_amount: u64,
-    pub counter: u64,
+    pub buying_timestamp: u64, //latest purchase
+    pub vesting_amount: u64, //still locked, summed over all purchases
+    pub buying_amount: u64, //bought in total, summed over all purchases
+    pub purchase_count: u8,
+    pub purchases: [Purchase; MAX_PURCHASES],
+    pub revocable: bool, //owner can take back the unvested part with Revoke
+    pub terminated: bool, //revoked, can't buy or claim anymore
+    pub purchased_total: u64, //bought by this wallet over its lifetime, what the wallet cap and allocation count
 
 
+}
+impl UserState {
+    /// The purchases actually in use, oldest first.
+    pub fn purchases(&self) -> &[Purchase] {
+        &self.purchases[..self.purchase_count as usize]
+    }
 }
 impl Sealed for UserState {}
 impl IsInitialized for UserState {
@@ -121,45 +558,78 @@ impl IsInitialized for UserState {
     }
 }
 impl Pack for UserState {
-    const LEN: usize = 65;
+    const LEN: usize = 580;
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
         let src = array_ref![src, 0, UserState::LEN];
-        let (is_initialized, user, buying_timestamp, vesting_amount,buying_amount,counter) =
-            array_refs![src, 1, 32, 8, 8,8,8];
+        let (is_initialized, user, buying_timestamp, vesting_amount,buying_amount,purchase_count,purchases_src,revocable,terminated,purchased_total) =
+            array_refs![src, 1, 32, 8, 8,8,1,Purchase::LEN * MAX_PURCHASES,1,1,8];
         let is_initialized = match is_initialized {
             [0] => false,
             [1] => true,
             _ => return Err(ProgramError::InvalidAccountData),
         };
+        if purchase_count[0] as usize > MAX_PURCHASES {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut purchases = [Purchase::default(); MAX_PURCHASES];
+        for (purchase, purchase_src) in purchases
+            .iter_mut()
+            .zip(purchases_src.chunks_exact(Purchase::LEN))
+        {
+            *purchase = Purchase::unpack_from_slice(array_ref![purchase_src, 0, Purchase::LEN]);
+        }
         Ok(UserState {
             is_initialized,
             user: Pubkey::new_from_array(*user),
             buying_timestamp: u64::from_le_bytes(*buying_timestamp),
             vesting_amount: u64::from_le_bytes(*vesting_amount),
             buying_amount: u64::from_le_bytes(*buying_amount),
-            counter: u64::from_le_bytes(*counter),
-
-
+            purchase_count: purchase_count[0],
+            purchases,
+            revocable: match revocable {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            terminated: match terminated {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            purchased_total: u64::from_le_bytes(*purchased_total),
         })
     }
     fn pack_into_slice(&self, dst: &mut [u8]) {
         let dst = array_mut_ref![dst, 0, UserState::LEN];
-        let (is_initialized_dst, user_dst, buying_timestamp_dst, vesting_amount_dst,buying_amount_dst,counter_dst) =
-            mut_array_refs![dst, 1, 32, 8, 8,8,8];
+        let (is_initialized_dst, user_dst, buying_timestamp_dst, vesting_amount_dst,buying_amount_dst,purchase_count_dst,purchases_dst,revocable_dst,terminated_dst,purchased_total_dst) =
+            mut_array_refs![dst, 1, 32, 8, 8,8,1,Purchase::LEN * MAX_PURCHASES,1,1,8];
         let UserState {
             is_initialized,
             user,
             buying_timestamp,
             vesting_amount,
             buying_amount,
-            counter,
+            purchase_count,
+            purchases,
+            revocable,
+            terminated,
+            purchased_total,
         } = self;
         is_initialized_dst[0] = *is_initialized as u8;
         user_dst.copy_from_slice(user.as_ref());
         *buying_timestamp_dst = buying_timestamp.to_le_bytes();
         *vesting_amount_dst = vesting_amount.to_le_bytes();
         *buying_amount_dst = buying_amount.to_le_bytes();
-        *counter_dst = counter.to_le_bytes();
+        purchase_count_dst[0] = *purchase_count;
+        for (purchase, purchase_dst) in purchases
+            .iter()
+            .zip(purchases_dst.chunks_exact_mut(Purchase::LEN))
+        {
+            purchase.pack_into_slice(array_mut_ref![purchase_dst, 0, Purchase::LEN]);
+        }
+        revocable_dst[0] = *revocable as u8;
+        terminated_dst[0] = *terminated as u8;
+        *purchased_total_dst = purchased_total.to_le_bytes();
 
 
     }
@@ -0,0 +1,54 @@
+use solana_program::{keccak::hashv, pubkey::Pubkey};
+use vesting_contract::processor::Processor;
+
+fn leaf(buyer: &Pubkey, allocation: u64) -> [u8; 32] {
+    hashv(&[buyer.as_ref(), &allocation.to_le_bytes()]).0
+}
+
+fn node(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+    if a <= b {
+        hashv(&[&a, &b]).0
+    } else {
+        hashv(&[&b, &a]).0
+    }
+}
+
+#[test]
+fn proof_of_a_four_leaf_tree() {
+    let buyers = [
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    ];
+    let leaves = [
+        leaf(&buyers[0], 1_000),
+        leaf(&buyers[1], 2_000),
+        leaf(&buyers[2], 0),
+        leaf(&buyers[3], 500),
+    ];
+    let left = node(leaves[0], leaves[1]);
+    let right = node(leaves[2], leaves[3]);
+    let root = node(left, right);
+
+    let proof = [leaves[1], right];
+    assert!(Processor::verify_merkle_proof(&proof, &root, leaves[0]));
+    assert!(Processor::verify_merkle_proof(&[leaves[2], left], &root, leaves[3]));
+
+    // the allocation is part of the leaf
+    assert!(!Processor::verify_merkle_proof(&proof, &root, leaf(&buyers[0], 2_000)));
+    // a wrong sibling on any level
+    assert!(!Processor::verify_merkle_proof(&[leaves[2], right], &root, leaves[0]));
+    assert!(!Processor::verify_merkle_proof(&[leaves[1], left], &root, leaves[0]));
+    // someone else's proof
+    assert!(!Processor::verify_merkle_proof(&proof, &root, leaf(&Pubkey::new_unique(), 1_000)));
+}
+
+#[test]
+fn empty_proof_only_for_a_single_leaf_tree() {
+    let buyer = Pubkey::new_unique();
+    let root = leaf(&buyer, 1_000);
+    assert!(Processor::verify_merkle_proof(&[], &root, leaf(&buyer, 1_000)));
+    assert!(!Processor::verify_merkle_proof(&[], &root, leaf(&buyer, 999)));
+    assert!(!Processor::verify_merkle_proof(&[], &root, leaf(&Pubkey::new_unique(), 1_000)));
+}
@@ -0,0 +1,11 @@
+use solana_program::program_pack::Pack;
+use vesting_contract::state::PlatForm;
+
+/// A zeroed platform priced at `token_price / token_price_den`, both mints
+/// without decimals. Tests set whatever else they need on it.
+pub fn platform(token_price: u64, token_price_den: u64) -> PlatForm {
+    let mut platform = PlatForm::unpack_unchecked(&[0; PlatForm::LEN]).unwrap();
+    platform.token_price = token_price;
+    platform.token_price_den = token_price_den;
+    platform
+}
@@ -0,0 +1,282 @@
+mod common;
+
+use common::platform;
+use solana_program::{clock::Clock, program_error::ProgramError, program_pack::Pack};
+use vesting_contract::{
+    error::FarmError,
+    processor::Processor,
+    state::{FeeMode, PlatForm, UserState, VestingMode, VestingTranche},
+};
+
+fn overflow() -> ProgramError {
+    FarmError::NumericOverflow.into()
+}
+
+#[test]
+fn sale_amounts_at_u64_max() {
+    let platform = PlatForm {
+        init_stage: 10,
+        ..platform(1, 1)
+    };
+    let terms = platform.primary_payment_terms();
+    let (tokens, init_stage, paid, fee_tokens) =
+        Processor::sale_amounts(&platform, &terms, u64::MAX).unwrap();
+    assert_eq!(tokens, u64::MAX);
+    assert_eq!(init_stage, u64::MAX / 10);
+    assert_eq!(paid, u64::MAX);
+    assert_eq!(fee_tokens, 0);
+}
+
+#[test]
+fn sale_amounts_overflowing_price() {
+    let platform = PlatForm {
+        init_stage: 10,
+        ..platform(2, 1)
+    };
+    let terms = platform.primary_payment_terms();
+    assert_eq!(
+        Processor::sale_amounts(&platform, &terms, u64::MAX / 2 + 1),
+        Err(overflow())
+    );
+}
+
+#[test]
+fn sale_amounts_overflowing_fee() {
+    let platform = PlatForm {
+        fee_bps: 100,
+        ..platform(1, 1)
+    };
+    let terms = platform.primary_payment_terms();
+    assert_eq!(Processor::sale_amounts(&platform, &terms, u64::MAX), Err(overflow()));
+    let (_, _, paid, _) = Processor::sale_amounts(&platform, &terms, u64::MAX / 2).unwrap();
+    assert_eq!(paid, u64::MAX / 2 + u64::MAX / 200);
+}
+
+#[test]
+fn fee_in_basis_points_rounds_down() {
+    let platform = PlatForm {
+        fee_bps: 25,
+        ..platform(1, 1)
+    };
+    let terms = platform.primary_payment_terms();
+    let (_, _, paid, _) = Processor::sale_amounts(&platform, &terms, 10_000).unwrap();
+    assert_eq!(paid, 10_025);
+    let (_, _, paid, _) = Processor::sale_amounts(&platform, &terms, 399).unwrap();
+    assert_eq!(paid, 399);
+    let (_, _, paid, _) = Processor::sale_amounts(&platform, &terms, 400).unwrap();
+    assert_eq!(paid, 401);
+}
+
+#[test]
+fn fee_withheld_from_vesting_tokens() {
+    let platform = PlatForm {
+        init_stage: 10,
+        fee_bps: 250,
+        fee_mode: FeeMode::VestingToken,
+        ..platform(10, 1)
+    };
+    let terms = platform.primary_payment_terms();
+    let (tokens, init_stage, paid, fee_tokens) =
+        Processor::sale_amounts(&platform, &terms, 1_001).unwrap();
+    // 10_010 tokens bought, 2.5% of them is 250.25 rounded down
+    assert_eq!(fee_tokens, 250);
+    assert_eq!(tokens, 9_760);
+    assert_eq!(init_stage, 976);
+    assert_eq!(paid, 1_001);
+}
+
+#[test]
+fn vesting_token_fee_above_the_tokens_overflows() {
+    let platform = PlatForm {
+        fee_bps: 10_001,
+        fee_mode: FeeMode::VestingToken,
+        ..platform(1, 1)
+    };
+    let terms = platform.primary_payment_terms();
+    assert_eq!(
+        Processor::sale_amounts(&platform, &terms, 10_000),
+        Err(overflow())
+    );
+}
+
+#[test]
+fn vesting_token_fee_is_rejected_with_a_soft_cap() {
+    let mut platform = PlatForm {
+        fee_bps: 100,
+        ..platform(1, 1)
+    };
+    platform.fee_mode = FeeMode::VestingToken;
+    assert_eq!(Processor::check_fee(&platform), Ok(()));
+    platform.soft_cap = 1;
+    assert_eq!(
+        Processor::check_fee(&platform),
+        Err(FarmError::InvalidFee.into())
+    );
+}
+
+#[test]
+fn fee_above_the_payment_is_rejected() {
+    let mut platform = platform(1, 1);
+    platform.fee_bps = 10_000;
+    assert_eq!(Processor::check_fee(&platform), Ok(()));
+    platform.fee_bps = 10_001;
+    assert_eq!(
+        Processor::check_fee(&platform),
+        Err(FarmError::InvalidFee.into())
+    );
+}
+
+#[test]
+fn mul_div_keeps_the_u128_intermediate() {
+    assert_eq!(Processor::mul_div(u64::MAX, 100, 100), Ok(u64::MAX));
+    assert_eq!(Processor::mul_div(u64::MAX, 3, 4), Ok(u64::MAX / 4 * 3 + 2));
+    assert_eq!(Processor::mul_div(u64::MAX, 101, 100), Err(overflow()));
+    assert_eq!(Processor::mul_div(1, 1, 0), Err(overflow()));
+}
+
+#[test]
+fn linear_unlock_at_u64_max() {
+    let mut platform = platform(1, 1);
+    platform.vesting_mode = VestingMode::Linear;
+    platform.vesting_period = u64::MAX;
+    assert_eq!(
+        Processor::unlocked_amount(&platform, u64::MAX, u64::MAX / 2),
+        Ok(u64::MAX / 2)
+    );
+    assert_eq!(
+        Processor::unlocked_amount(&platform, u64::MAX, u64::MAX),
+        Ok(u64::MAX)
+    );
+}
+
+#[test]
+fn staged_unlock_at_u64_max() {
+    let mut platform = PlatForm {
+        init_stage: 50,
+        ..platform(1, 1)
+    };
+    platform.schedule_len = 2;
+    platform.schedule[0] = VestingTranche { offset: 10, percentage: 25 };
+    platform.schedule[1] = VestingTranche { offset: 20, percentage: 25 };
+    assert_eq!(
+        Processor::unlocked_amount(&platform, u64::MAX, 10),
+        Ok(u64::MAX / 4 * 3 + 2)
+    );
+    assert_eq!(Processor::unlocked_amount(&platform, u64::MAX, 20), Ok(u64::MAX));
+}
+
+#[test]
+fn staged_cliff_holds_back_tranches() {
+    let mut platform = PlatForm {
+        init_stage: 10,
+        cliff: 45,
+        ..platform(1, 1)
+    };
+    platform.schedule_len = 2;
+    platform.schedule[0] = VestingTranche { offset: 30, percentage: 45 };
+    platform.schedule[1] = VestingTranche { offset: 60, percentage: 45 };
+    // only the init stage before the cliff, even past the first offset
+    assert_eq!(Processor::unlocked_amount(&platform, 1_000, 0), Ok(100));
+    assert_eq!(Processor::unlocked_amount(&platform, 1_000, 44), Ok(100));
+    // the tranches passed during the cliff come out at once
+    assert_eq!(Processor::unlocked_amount(&platform, 1_000, 45), Ok(550));
+    assert_eq!(Processor::unlocked_amount(&platform, 1_000, 60), Ok(1_000));
+}
+
+#[test]
+fn linear_cliff_releases_a_lump() {
+    let platform = PlatForm {
+        init_stage: 20,
+        cliff: 40,
+        vesting_period: 100,
+        vesting_mode: VestingMode::Linear,
+        ..platform(1, 1)
+    };
+    assert_eq!(Processor::unlocked_amount(&platform, 1_000, 39), Ok(200));
+    // 40% of the locked 800 accrued during the cliff
+    assert_eq!(Processor::unlocked_amount(&platform, 1_000, 40), Ok(520));
+    assert_eq!(Processor::unlocked_amount(&platform, 1_000, 70), Ok(760));
+    assert_eq!(Processor::unlocked_amount(&platform, 1_000, 100), Ok(1_000));
+}
+
+#[test]
+fn staged_schedule_must_add_up_to_100_in_order() {
+    let mut platform = PlatForm {
+        init_stage: 10,
+        ..platform(1, 1)
+    };
+    let monthly = [
+        VestingTranche { offset: 30, percentage: 45 },
+        VestingTranche { offset: 60, percentage: 45 },
+    ];
+    assert_eq!(Processor::set_schedule(&mut platform, &monthly), Ok(()));
+    assert_eq!(platform.schedule(), &monthly);
+
+    let out_of_order = [monthly[1], monthly[0]];
+    assert_eq!(
+        Processor::set_schedule(&mut platform, &out_of_order),
+        Err(FarmError::InvalidSchedule.into())
+    );
+    let short = [monthly[0]];
+    assert_eq!(
+        Processor::set_schedule(&mut platform, &short),
+        Err(FarmError::InvalidSchedule.into())
+    );
+    let over = [monthly[0], VestingTranche { offset: 60, percentage: 46 }];
+    assert_eq!(
+        Processor::set_schedule(&mut platform, &over),
+        Err(FarmError::InvalidSchedule.into())
+    );
+}
+
+#[test]
+fn staged_schedule_cant_be_empty() {
+    let mut platform = platform(1, 1);
+    platform.init_stage = 100;
+    assert_eq!(
+        Processor::set_schedule(&mut platform, &[]),
+        Err(FarmError::InvalidSchedule.into())
+    );
+    // linear mode vests over vesting_period and needs no tranches
+    platform.vesting_mode = VestingMode::Linear;
+    assert_eq!(Processor::set_schedule(&mut platform, &[]), Ok(()));
+}
+
+#[test]
+fn claim_before_purchase_is_clock_skew() {
+    let platform = platform(1, 1);
+    let mut user = UserState::unpack_unchecked(&[0; UserState::LEN]).unwrap();
+    user.purchase_count = 1;
+    user.purchases[0].buying_timestamp = 100;
+    user.purchases[0].buying_amount = 10;
+    user.buying_amount = 10;
+    user.vesting_amount = 10;
+    assert_eq!(
+        Processor::claimable_amount(&platform, &user, 99),
+        Err(FarmError::ClockSkew.into())
+    );
+}
+
+#[test]
+fn claim_with_more_locked_than_bought_overflows() {
+    let platform = platform(1, 1);
+    let mut user = UserState::unpack_unchecked(&[0; UserState::LEN]).unwrap();
+    user.buying_amount = 1;
+    user.vesting_amount = 2;
+    assert_eq!(
+        Processor::claimable_amount(&platform, &user, 0),
+        Err(overflow())
+    );
+}
+
+#[test]
+fn negative_clock_is_clock_skew() {
+    let clock = Clock {
+        unix_timestamp: -1,
+        ..Clock::default()
+    };
+    assert_eq!(
+        Processor::unix_timestamp(&clock),
+        Err(FarmError::ClockSkew.into())
+    );
+}
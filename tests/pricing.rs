@@ -0,0 +1,139 @@
+mod common;
+
+use common::platform;
+use solana_program::pubkey::Pubkey;
+use vesting_contract::{
+    error::FarmError,
+    processor::Processor,
+    state::{PaymentMint, PlatForm},
+};
+
+#[test]
+fn fractional_price_with_equal_decimals() {
+    // 1 USDC = 0.35 tokens, both mints with 6 decimals
+    let platform = PlatForm {
+        payment_decimals: 6,
+        vesting_decimals: 6,
+        ..platform(7, 20)
+    };
+    let terms = platform.primary_payment_terms();
+    assert_eq!(Processor::token_amount(&platform, &terms, 1_000_000), Ok(350_000));
+    assert_eq!(Processor::token_amount(&platform, &terms, 20), Ok(7));
+}
+
+#[test]
+fn decimals_are_normalized() {
+    // 1 USDC (6 decimals) = 10 tokens (9 decimals)
+    let platform = PlatForm {
+        payment_decimals: 6,
+        vesting_decimals: 9,
+        ..platform(10, 1)
+    };
+    assert_eq!(
+        Processor::token_amount(&platform, &platform.primary_payment_terms(), 1_000_000),
+        Ok(10_000_000_000)
+    );
+
+    // 1 token (9 decimals) = 2 tokens (6 decimals)
+    let platform = PlatForm {
+        payment_decimals: 9,
+        vesting_decimals: 6,
+        ..self::platform(2, 1)
+    };
+    assert_eq!(
+        Processor::token_amount(&platform, &platform.primary_payment_terms(), 1_000_000_000),
+        Ok(2_000_000)
+    );
+}
+
+#[test]
+fn rounds_down_in_favour_of_the_vault() {
+    let platform = PlatForm {
+        payment_decimals: 6,
+        vesting_decimals: 6,
+        ..platform(7, 20)
+    };
+    let terms = platform.primary_payment_terms();
+    // 0.35 * 19 = 6.65 base units
+    assert_eq!(Processor::token_amount(&platform, &terms, 19), Ok(6));
+    // 0.35 * 2 = 0.7 base units
+    assert_eq!(Processor::token_amount(&platform, &terms, 2), Ok(0));
+
+    let platform = self::platform(1, 3);
+    let terms = platform.primary_payment_terms();
+    assert_eq!(Processor::token_amount(&platform, &terms, 2), Ok(0));
+    assert_eq!(Processor::token_amount(&platform, &terms, 3), Ok(1));
+}
+
+#[test]
+fn price_overflow_is_an_error() {
+    let platform = PlatForm {
+        vesting_decimals: 9,
+        ..platform(u64::MAX, 1)
+    };
+    assert_eq!(
+        Processor::token_amount(&platform, &platform.primary_payment_terms(), u64::MAX),
+        Err(FarmError::NumericOverflow.into())
+    );
+    let platform = PlatForm {
+        vesting_decimals: 40,
+        ..self::platform(1, 1)
+    };
+    assert_eq!(
+        Processor::token_amount(&platform, &platform.primary_payment_terms(), 1),
+        Err(FarmError::NumericOverflow.into())
+    );
+}
+
+#[test]
+fn price_needs_both_sides() {
+    assert_eq!(
+        Processor::check_price(&platform(0, 1).primary_payment_terms()),
+        Err(FarmError::InvalidPrice.into())
+    );
+    assert_eq!(
+        Processor::check_price(&platform(1, 0).primary_payment_terms()),
+        Err(FarmError::InvalidPrice.into())
+    );
+    assert_eq!(Processor::check_price(&platform(7, 20).primary_payment_terms()), Ok(()));
+}
+
+#[test]
+fn each_payment_mint_has_its_own_price() {
+    // 1 USDC (6 decimals) = 10 tokens, 1 PYUSD (6 decimals) = 9.5 tokens, 9 decimal tokens
+    let mut platform = PlatForm {
+        payment_decimals: 6,
+        vesting_decimals: 9,
+        ..platform(10, 1)
+    };
+    platform.payment_mint = Pubkey::new_unique();
+    let pyusd = PaymentMint {
+        mint: Pubkey::new_unique(),
+        decimals: 6,
+        token_price: 19,
+        token_price_den: 2,
+        treasury: Pubkey::new_unique(),
+    };
+    platform.payment_mints[0] = pyusd;
+    platform.payment_mint_count = 1;
+
+    let usdc = platform.payment_terms(&platform.payment_mint).unwrap();
+    assert_eq!(
+        Processor::token_amount(&platform, &usdc, 1_000_000),
+        Ok(10_000_000_000)
+    );
+    assert_eq!(platform.payment_terms(&pyusd.mint), Some(pyusd));
+    assert_eq!(
+        Processor::token_amount(&platform, &pyusd, 1_000_000),
+        Ok(9_500_000_000)
+    );
+}
+
+#[test]
+fn unknown_payment_mint_is_not_accepted() {
+    let mut platform = platform(10, 1);
+    platform.payment_mints[0].mint = Pubkey::new_unique();
+    // entries past payment_mint_count don't count
+    assert_eq!(platform.payment_terms(&platform.payment_mints[0].mint), None);
+    assert_eq!(platform.payment_terms(&Pubkey::new_unique()), None);
+}